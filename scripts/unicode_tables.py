@@ -0,0 +1,121 @@
+#!/usr/bin/env python3
+"""
+Generate src/regex/unicode_tables.rs from the Unicode database shipped with Python.
+
+    python3 scripts/unicode_tables.py > src/regex/unicode_tables.rs
+"""
+import sys
+import unicodedata
+
+# White_Space from PropList.txt, it is not exposed by unicodedata
+WHITE_SPACE = [
+    (0x09, 0x0D), (0x20, 0x20), (0x85, 0x85), (0xA0, 0xA0), (0x1680, 0x1680),
+    (0x2000, 0x200A), (0x2028, 0x2029), (0x202F, 0x202F), (0x205F, 0x205F),
+    (0x3000, 0x3000),
+]
+# Join_Control, part of \w in UTS#18
+JOIN_CONTROL = [(0x200C, 0x200D)]
+# long names from PropertyValueAliases.txt
+ALIASES = {
+    "C": "Other", "Cc": "Control", "Cf": "Format", "Cn": "Unassigned", "Co": "Private_Use",
+    "L": "Letter", "Ll": "Lowercase_Letter", "Lm": "Modifier_Letter", "Lo": "Other_Letter",
+    "Lt": "Titlecase_Letter", "Lu": "Uppercase_Letter", "M": "Mark", "Mc": "Spacing_Mark",
+    "Me": "Enclosing_Mark", "Mn": "Nonspacing_Mark", "N": "Number", "Nd": "Decimal_Number",
+    "Nl": "Letter_Number", "No": "Other_Number", "P": "Punctuation",
+    "Pc": "Connector_Punctuation", "Pd": "Dash_Punctuation", "Pe": "Close_Punctuation",
+    "Pf": "Final_Punctuation", "Pi": "Initial_Punctuation", "Po": "Other_Punctuation",
+    "Ps": "Open_Punctuation", "S": "Symbol", "Sc": "Currency_Symbol", "Sk": "Modifier_Symbol",
+    "Sm": "Math_Symbol", "So": "Other_Symbol", "Z": "Separator", "Zl": "Line_Separator",
+    "Zp": "Paragraph_Separator", "Zs": "Space_Separator",
+}
+
+
+def chars():
+    for cp in range(0x110000):
+        if 0xD800 <= cp <= 0xDFFF:
+            continue
+        yield cp
+
+
+def ranges(cps):
+    result = []
+    for cp in sorted(cps):
+        if result and (result[-1][1] + 1 == cp or (result[-1][1] == 0xD7FF and cp == 0xE000)):
+            result[-1][1] = cp
+        else:
+            result.append([cp, cp])
+    return result
+
+
+def merge(*tables):
+    cps = set()
+    for table in tables:
+        for lo, hi in table:
+            cps.update(cp for cp in range(lo, hi + 1) if not 0xD800 <= cp <= 0xDFFF)
+    return ranges(cps)
+
+
+def simple_fold(c):
+    # casefold is the full folding, fall back on the simple lowercase when it is not one char
+    for folded in (c.casefold(), c.lower()):
+        if len(folded) == 1:
+            return folded
+    return c
+
+
+def case_orbits():
+    orbits = {}
+    for cp in chars():
+        orbits.setdefault(simple_fold(chr(cp)), set()).add(cp)
+    table = {}
+    for members in orbits.values():
+        if len(members) > 1:
+            for cp in members:
+                table[cp] = sorted(members - {cp})
+    return sorted(table.items())
+
+
+def rust_char(cp):
+    return "'\\u{%X}'" % cp
+
+
+def emit_table(name, table, out):
+    out.write("pub(crate) const %s: Table = &[\n" % name)
+    for lo, hi in table:
+        out.write("    (%s, %s),\n" % (rust_char(lo), rust_char(hi)))
+    out.write("];\n\n")
+
+
+def main(out):
+    categories = {}
+    for cp in chars():
+        categories.setdefault(unicodedata.category(chr(cp)), []).append(cp)
+    tables = {name: ranges(cps) for name, cps in categories.items()}
+    for group in sorted({name[0] for name in categories}):
+        tables[group] = merge(*(t for n, t in tables.items() if len(n) == 2 and n[0] == group))
+
+    out.write("// DO NOT EDIT, generated by scripts/unicode_tables.py\n")
+    out.write("// Unicode version %s\n\n" % unicodedata.unidata_version)
+    out.write("pub(crate) type Table = &'static [(char, char)];\n\n")
+    names = sorted(tables)
+    # (short name, long name, table), sorted by the short name
+    out.write("pub(crate) const GENERAL_CATEGORY: &[(&str, &str, Table)] = &[\n")
+    for name in names:
+        out.write("    (\"%s\", \"%s\", GC_%s),\n" % (name, ALIASES[name], name.upper()))
+    out.write("];\n\n")
+    for name in names:
+        emit_table("GC_%s" % name.upper(), tables[name], out)
+
+    word = merge(tables["L"], tables["M"], tables["Nd"], tables["Pc"], JOIN_CONTROL)
+    emit_table("PERL_WORD", word, out)
+    emit_table("PERL_SPACE", WHITE_SPACE, out)
+
+    # every char with the other chars of its simple case folding orbit
+    out.write("pub(crate) const CASE_FOLD: &[(char, &[char])] = &[\n")
+    for cp, others in case_orbits():
+        out.write("    (%s, &[%s]),\n" % (rust_char(cp), ", ".join(rust_char(o) for o in others)))
+    out.write("];\n")
+
+
+if __name__ == "__main__":
+    main(sys.stdout)
@@ -1,5 +1,7 @@
 use core::cmp::Ordering;
 use core::hash::Hash;
+use std::collections::BTreeMap;
+
 #[derive(Debug, Eq, Copy, Clone)]
 pub enum Action<V>
 where
@@ -49,27 +51,7 @@ where
     V: Hash + Eq + Ord + Copy,
 {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        match (self, other) {
-            (Action::Range(v1, v2), Action::Range(s1, s2)) => {
-                assert!(v2 > v1 && s2 > s1);
-                Some(if v1 == s1 && v2 == s2 {
-                    Ordering::Equal
-                } else if v2 > s2 {
-                    Ordering::Greater
-                } else {
-                    Ordering::Less
-                })
-            }
-            (Action::Range(s, e), Action::Single(c)) => Some(if s <= c && c <= e {
-                Ordering::Equal
-            } else if c > e {
-                Ordering::Greater
-            } else {
-                Ordering::Less
-            }),
-            (Action::Single(v1), Action::Single(v2)) => v1.partial_cmp(v2),
-            (v, s) => s.partial_cmp(v),
-        }
+        Some(self.cmp(other))
     }
 }
 
@@ -99,29 +81,208 @@ where
 {
     fn cmp(&self, other: &Self) -> Ordering {
         match (self, other) {
-            (Action::Range(v1, v2), Action::Range(s1, s2)) => {
-                assert!(v2 > v1 && s2 > s1);
-                if v1 == s1 && v2 == s2 {
-                    Ordering::Equal
-                } else if v2 > s2 {
-                    Ordering::Greater
-                } else {
-                    Ordering::Less
-                }
-            }
+            // two ranges compare by their start then their end, a total order even when they overlap
+            (Action::Range(v1, v2), Action::Range(s1, s2)) => (v1, v2).cmp(&(s1, s2)),
+            // a range is above every value before it and below every value after it
             (Action::Range(s, e), Action::Single(c)) => {
                 if s <= c && c <= e {
                     Ordering::Equal
                 } else if c > e {
-                    Ordering::Greater
-                } else {
                     Ordering::Less
+                } else {
+                    Ordering::Greater
                 }
             }
             (Action::Single(v1), Action::Single(v2)) => v1.cmp(v2),
-            (v, s) => s.cmp(v),
+            (v, s) => s.cmp(v).reverse(),
         }
     }
 }
+
+// the symbol right after and right before, for cutting a range in two
+pub trait Step: Sized {
+    fn next(&self) -> Option<Self>;
+    fn prev(&self) -> Option<Self>;
+}
+
+impl Step for char {
+    #[inline]
+    fn next(&self) -> Option<Self> {
+        crate::regex::next_char(*self)
+    }
+    #[inline]
+    fn prev(&self) -> Option<Self> {
+        crate::regex::prev_char(*self)
+    }
+}
+
+/*
+ * the labels of a Nfa are cut into the elementary labels: the disjoint pieces such that every
+ * label is a union of some of them, then the subset construction reads the edges of all the
+ * states of a set with the same pieces, like `[a-z]` and `e` leaving two states of `[a-z]x|ey`.
+ *
+ * a plain symbol is a piece of its own, which the provided methods assume, so an empty impl is
+ * enough for a symbol type that is not a range
+ */
+pub trait Partition: Ord + Copy {
+    fn partition(labels: &[Self]) -> Vec<Self> {
+        let mut labels = labels.to_vec();
+        labels.sort_unstable();
+        labels.dedup();
+        labels
+    }
+    // the first and the last symbol of a label, the pieces overlapping it are ordered between them
+    #[inline]
+    fn bounds(&self) -> (Self, Self) {
+        (*self, *self)
+    }
+}
+
+macro_rules! symbols {
+    ($($t:ty),+) => {
+        $(
+            impl Step for $t {
+                #[inline]
+                fn next(&self) -> Option<Self> {
+                    self.checked_add(1)
+                }
+                #[inline]
+                fn prev(&self) -> Option<Self> {
+                    self.checked_sub(1)
+                }
+            }
+            impl Partition for $t {}
+        )+
+    };
+}
+
+symbols!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+impl Partition for char {}
+
+impl<V> Partition for Action<V>
+where
+    V: Hash + Eq + Ord + Copy + Step,
+{
+    fn partition(labels: &[Self]) -> Vec<Self> {
+        // +1 where a label starts and -1 right after it stops, a piece starts at each of them
+        let mut bounds: BTreeMap<V, isize> = BTreeMap::new();
+        let mut last = None;
+        for label in labels {
+            let (start, end) = ends(label);
+            *bounds.entry(start).or_default() += 1;
+            if let Some(next) = end.next() {
+                *bounds.entry(next).or_default() -= 1;
+            }
+            last = last.max(Some(end));
+        }
+        let bounds: Vec<(V, isize)> = bounds.into_iter().collect();
+        let mut covered = 0;
+        let mut pieces = Vec::new();
+        for (i, &(start, delta)) in bounds.iter().enumerate() {
+            covered += delta;
+            if covered > 0 {
+                // the pieces still covered at the last bound run to the highest end
+                let end = bounds.get(i + 1).and_then(|(next, _)| next.prev()).or(last);
+                pieces.push(match end.unwrap() {
+                    end if end == start => Action::Single(start),
+                    end => Action::Range(start, end),
+                });
+            }
+        }
+        pieces
+    }
+    #[inline]
+    fn bounds(&self) -> (Self, Self) {
+        let (start, end) = ends(self);
+        (Action::Single(start), Action::Single(end))
+    }
+}
+
+#[inline]
+fn ends<V>(label: &Action<V>) -> (V, V)
+where
+    V: Hash + Eq + Ord + Copy,
+{
+    match *label {
+        Action::Range(start, end) => (start, end),
+        Action::Single(v) => (v, v),
+    }
+}
+
 #[cfg(test)]
-mod action_test {}
+mod action_test {
+    use std::{cmp::Ordering, collections::BTreeMap};
+
+    use super::{Action, Partition};
+
+    #[test]
+    fn range_single_order() {
+        assert!(Action::Range('b', 'd') > Action::Single('a'));
+        assert!(Action::Range('b', 'd') < Action::Single('e'));
+        assert!(Action::Single('a') < Action::Range('b', 'd'));
+        assert!(Action::Single('e') > Action::Range('b', 'd'));
+        assert_eq!(Action::Single('c'), Action::Range('b', 'd'));
+    }
+    #[test]
+    fn disjoint_lookup() {
+        // the insertion order must not matter for a map of disjoint labels
+        let map: BTreeMap<_, _> = [
+            (Action::Single('z'), 3),
+            (Action::Range('a', 'c'), 1),
+            (Action::Single('x'), 2),
+            (Action::Range('0', '9'), 0),
+        ]
+        .into();
+        assert_eq!(map.get(&Action::Single('b')), Some(&1));
+        assert_eq!(map.get(&Action::Single('5')), Some(&0));
+        assert_eq!(map.get(&Action::Single('x')), Some(&2));
+        assert_eq!(map.get(&Action::Single('z')), Some(&3));
+        assert_eq!(map.get(&Action::Single('y')), None);
+        // a range only finds itself, overlapping ranges are ordered by their start then their end
+        assert_eq!(map.get(&Action::Range('a', 'c')), Some(&1));
+        assert_eq!(map.get(&Action::Range('a', 'b')), None);
+        assert!(Action::Range('a', 'c') < Action::Range('b', 'd'));
+        assert!(Action::Range('b', 'd') > Action::Range('a', 'c'));
+        assert_eq!(
+            Action::Range('a', 'c').cmp(&Action::Range('b', 'c')),
+            Ordering::Less
+        );
+        assert_eq!(
+            Action::Range('b', 'c').cmp(&Action::Range('a', 'c')),
+            Ordering::Greater
+        );
+        assert_eq!(
+            Action::Range('a', 'b').cmp(&Action::Range('a', 'c')),
+            Ordering::Less
+        );
+    }
+    #[test]
+    fn partition() {
+        let labels = [
+            Action::Range('a', 'z'),
+            Action::Single('e'),
+            Action::Range('x', '~'),
+            Action::Single('0'),
+            Action::Single('0'),
+            Action::Range('\u{10FFF0}', char::MAX),
+        ];
+        assert_eq!(
+            Action::partition(&labels),
+            [
+                Action::Single('0'),
+                Action::Range('a', 'd'),
+                Action::Single('e'),
+                Action::Range('f', 'w'),
+                Action::Range('x', 'z'),
+                Action::Range('{', '~'),
+                Action::Range('\u{10FFF0}', char::MAX),
+            ]
+        );
+        assert_eq!(
+            Action::partition(&[Action::Range(0u8, 255), Action::Single(0)]),
+            [Action::Single(0), Action::Range(1, 255)]
+        );
+        assert_eq!(u8::partition(&[3, 1, 3]), [1, 3]);
+    }
+}
@@ -0,0 +1,221 @@
+use std::{collections::HashSet, hash::Hash};
+
+use super::{Captures, Context, Nfa, StateMachine, WordSymbol};
+use crate::matches::{Input, Matcher};
+
+// enough for a few thousand symbols through a few hundred states
+pub const DEFAULT_BUDGET: usize = 1 << 20;
+
+#[derive(Debug)]
+pub enum BacktrackError {
+    // the steps allowed are all taken before an answer, the Pike VM gives one in linear time
+    BudgetExceeded,
+}
+
+/*
+ * a depth first search over the paths of the Nfa, in the same order of priority as the Pike VM,
+ * so the same leftmost first match and captures. A (state, position) pair that failed once fails
+ * again whatever the path to it, so every pair is visited once and the search takes at most
+ * states·(symbols+1) steps, no exponential blow up, but as much memory for the visited pairs.
+ * That is small on a short input, where following one path is faster than moving a set of
+ * threads, and the budget stops it on a long one.
+ *
+ * the symbols are read from the Input as the search reaches them and kept, a failed start is
+ * retried one symbol later without reading them again. The spans are offsets into the whole
+ * input and the symbols after a match are left to the next one, as the Pike VM does.
+ *
+ * the Matcher gives `Some(Err(_))` when the budget is exceeded, then the input goes on right
+ * after the start the search stopped at.
+ */
+pub struct Backtracker<'a, S, V>
+where
+    S: Hash + Eq + Ord + Copy,
+    V: Hash + Eq + Ord,
+{
+    nfa: &'a Nfa<S, V>,
+    slots: usize,
+    budget: usize,
+}
+
+// what is left to do on the way down and back up
+enum Frame<S> {
+    Explore(S, usize),
+    Restore(usize, Option<usize>),
+}
+
+impl<'a, S, V> Backtracker<'a, S, V>
+where
+    S: Hash + Eq + Ord + Copy,
+    V: Hash + Eq + Ord + Copy + WordSymbol,
+{
+    #[inline]
+    pub fn new(nfa: &'a Nfa<S, V>) -> Self {
+        Self {
+            nfa,
+            slots: 2 * nfa.captures_len(),
+            budget: DEFAULT_BUDGET,
+        }
+    }
+    // the states at most visited by a call
+    #[inline]
+    pub fn budget(mut self, steps: usize) -> Self {
+        self.budget = steps;
+        self
+    }
+    pub fn try_match<I>(&self, input: &mut Input<I>) -> Result<Option<Captures>, BacktrackError>
+    where
+        I: Iterator<Item = V>,
+    {
+        let mut visited = HashSet::new();
+        let mut steps = 0;
+        let mut start = input.offset();
+        loop {
+            input.advance_to(start);
+            let mut slots = vec![None; self.slots];
+            slots[0] = Some(start);
+            let mut stack: Vec<_> = self
+                .nfa
+                .start_states
+                .iter()
+                .rev()
+                .map(|state| Frame::Explore(*state, start))
+                .collect();
+            while let Some(frame) = stack.pop() {
+                let (state, pos) = match frame {
+                    Frame::Explore(state, pos) => (state, pos),
+                    Frame::Restore(slot, old) => {
+                        slots[slot] = old;
+                        continue;
+                    }
+                };
+                if !visited.insert((state, pos)) {
+                    continue;
+                }
+                steps += 1;
+                if steps > self.budget {
+                    input.advance_to(start + 1);
+                    return Err(BacktrackError::BudgetExceeded);
+                }
+                for &slot in self.nfa.slots(&state) {
+                    if let Some(old) = slots.get(slot).copied() {
+                        stack.push(Frame::Restore(slot, old));
+                        slots[slot] = Some(pos);
+                    }
+                }
+                if self.nfa.is_end(&state) {
+                    slots[1] = Some(pos);
+                    // an empty match takes the symbol after it, so `matches` goes forward
+                    input.advance_to(pos.max(start + 1));
+                    return Ok(Some(Captures::new(slots)));
+                }
+                let prev = pos.checked_sub(1).and_then(|pos| input.get(pos));
+                let current = input.get(pos);
+                let (before, after) = (Context::of(prev.as_ref()), Context::of(current.as_ref()));
+                // the symbol edges are tried before the zero width ones, as the Pike VM does
+                let zero_width = self.nfa.zero_width(&state, before, after);
+                stack.extend(
+                    zero_width
+                        .into_iter()
+                        .rev()
+                        .map(|to| Frame::Explore(to, pos)),
+                );
+                if let Some(v) = current {
+                    let moved = self.nfa.next_state(&state, &Some(v)).into_iter().flatten();
+                    stack.extend(moved.rev().map(|to| Frame::Explore(*to, pos + 1)));
+                }
+            }
+            if input.get(start).is_none() {
+                input.advance_to(start);
+                return Ok(None);
+            }
+            start += 1;
+        }
+    }
+}
+
+impl<'a, S, V, I> Matcher<Input<I>> for Backtracker<'a, S, V>
+where
+    I: Iterator<Item = V>,
+    S: Hash + Eq + Ord + Copy,
+    V: Hash + Eq + Ord + Copy + WordSymbol,
+{
+    type Matched = Result<Captures, BacktrackError>;
+    #[inline]
+    fn r#match(&self, input: &mut Input<I>) -> Option<Self::Matched> {
+        self.try_match(input).transpose()
+    }
+}
+
+#[cfg(test)]
+mod backtrack_test {
+    use super::{BacktrackError, Backtracker};
+    use crate::automate::{Action, PikeVm};
+    use crate::matches::{Input, Matcher};
+    use crate::regex::parse;
+
+    #[test]
+    fn same_as_pike_vm() {
+        let patterns = [
+            r"(\d+)-(\d+)",
+            "(a|ab)(c|bcd)(d*)",
+            "((a)|b)+",
+            "(a)|b",
+            r"\b(\w+)\b",
+            "(a*)*(b)",
+            "x(y?)$|(z)",
+        ];
+        let inputs = [
+            "tel 12-345",
+            "abcd",
+            "ab",
+            "b",
+            "-- word",
+            "aab",
+            "zxy",
+            "xy",
+            "",
+        ];
+        for pattern in patterns {
+            let nfa = parse(pattern).unwrap();
+            let (backtracker, vm) = (Backtracker::new(&nfa), PikeVm::new(&nfa));
+            for input in inputs {
+                let symbols = || Input::new(input.chars().map(Action::Single));
+                assert_eq!(
+                    backtracker.try_match(&mut symbols()).unwrap(),
+                    vm.r#match(&mut symbols()),
+                    "{} on {:?}",
+                    pattern,
+                    input
+                );
+                // and the matches after it, from where the first one left the input
+                let mut input = symbols();
+                let all: Vec<_> = backtracker
+                    .matches(&mut input)
+                    .map(Result::unwrap)
+                    .collect();
+                assert_eq!(all, vm.matches(&mut symbols()).collect::<Vec<_>>());
+            }
+        }
+    }
+    #[test]
+    fn budget() {
+        // every (state, position) pair at most once, far from the 2^n paths of `(a|a)*b`
+        let nfa = parse("(a|a)*b").unwrap();
+        let input = "a".repeat(30);
+        let symbols = || Input::new(input.chars().map(Action::Single));
+        assert!(matches!(
+            Backtracker::new(&nfa).try_match(&mut symbols()),
+            Ok(None)
+        ));
+        assert!(matches!(
+            Backtracker::new(&nfa).budget(100).r#match(&mut symbols()),
+            Some(Err(BacktrackError::BudgetExceeded))
+        ));
+        // the symbols after the match are not taken
+        let nfa = parse("ab").unwrap();
+        let mut input = Input::new("xabyz".chars().map(Action::Single));
+        let captures = Backtracker::new(&nfa).r#match(&mut input);
+        assert_eq!(captures.unwrap().unwrap().get(0), Some(1..3));
+        assert_eq!(input.next(), Some(Action::Single('y')));
+    }
+}
@@ -1,16 +1,26 @@
-use super::Nfa;
-use super::StateMachine;
+use super::{Context, Look, Nfa, Partition, StateMachine, Step, WordSymbol};
 use crate::matches::Matcher;
 
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
-    fmt::{Debug, Display},
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Debug,
     hash::Hash,
+    mem::{size_of, size_of_val},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
 #[derive(Debug)]
 pub enum DfaError {
     InvalidRelationship,
+    // the operation has no meaning for a Dfa whose acceptance depends on the symbols around it
+    HasLooks,
+    // past a limit of DfaBuilder
+    TooLarge,
+    // the cancel flag of DfaBuilder was set
+    Cancelled,
 }
 
 /*
@@ -32,8 +42,26 @@ where
     V: Hash + Eq + Ord,
 {
     start_state: S,
+    // the states accepting when the input ends right after them
     end_state: HashSet<S>,
     maped: BTreeMap<S, BTreeMap<V, S>>,
+    // only a Dfa built from a Nfa with look edges needs it
+    look: Option<LookTable<S>>,
+    // the word symbols for the look table, kept by DfaBuilder::build_with_looks, none otherwise
+    word: fn(&V) -> bool,
+}
+
+/*
+ * the look behind is tagged into the states, a state of the Dfa only knows what was before it, so
+ * a match not starting at the input start needs its own start state, and the look ahead is only
+ * known once the next symbol is, so the acceptance depends on the next symbol too
+ */
+#[derive(Debug, Clone)]
+struct LookTable<S> {
+    start_after_word: S,
+    start_after_other: S,
+    end_before_word: HashSet<S>,
+    end_before_other: HashSet<S>,
 }
 
 impl<S, V> Dfa<S, V>
@@ -47,6 +75,8 @@ where
             start_state,
             end_state: HashSet::new(),
             maped: Default::default(),
+            look: None,
+            word: |_| false,
         }
     }
     #[inline]
@@ -55,6 +85,8 @@ where
             start_state,
             end_state: HashSet::with_capacity(end_state_amount),
             maped: Default::default(),
+            look: None,
+            word: |_| false,
         }
     }
     #[inline]
@@ -85,39 +117,111 @@ where
         self.end_state.insert(node)
     }
     #[inline]
-    pub fn optimize(&mut self) {
-        // let mut result = HashSet::with_capacity(self.all_state.len());
-        // while !other_state.is_empty() && !self.end_state.is_empty() {}
-        // for i in 0..result.len() {}
+    pub fn start_state(&self) -> S {
+        self.start_state
     }
-}
-
-fn closure<'a, S: 'a, V>(nfa: &Nfa<S, V>, set: &mut impl Iterator<Item = &'a S>) -> BTreeSet<S>
-where
-    S: Hash + Ord + Copy + Eq,
-    V: Hash + Ord + Copy + Eq,
-{
-    let mut result = BTreeSet::new();
-    set.map(|state| nfa.closure(state)).for_each(|set| {
-        for state in set.iter() {
-            result.insert(*state);
+    pub fn edges(&self) -> impl Iterator<Item = (S, V, S)> + '_ {
+        self.maped
+            .iter()
+            .flat_map(|(from, map)| map.iter().map(move |(v, to)| (*from, *v, *to)))
+    }
+    /*
+     * removes the states the start does not reach and the states reaching no end, with the edges
+     * into them, returns how many there were. The start states stay, without any edge if they
+     * reach no end.
+     */
+    pub fn trim(&mut self) -> usize {
+        let mut backward: BTreeMap<S, Vec<S>> = BTreeMap::new();
+        for (from, _, to) in self.edges() {
+            backward.entry(to).or_default().push(from);
         }
-    });
-    result
+        let mut starts = vec![self.start_state];
+        let mut ends: Vec<S> = self.end_state.iter().copied().collect();
+        if let Some(look) = &self.look {
+            starts.extend([look.start_after_word, look.start_after_other]);
+            ends.extend(
+                look.end_before_word
+                    .iter()
+                    .chain(look.end_before_other.iter()),
+            );
+        }
+        let mut reached = BTreeSet::new();
+        let mut stack = starts.clone();
+        while let Some(state) = stack.pop() {
+            if reached.insert(state) {
+                stack.extend(
+                    self.maped
+                        .get(&state)
+                        .into_iter()
+                        .flat_map(|map| map.values()),
+                );
+            }
+        }
+        let mut reaching = BTreeSet::new();
+        let mut stack = ends.clone();
+        while let Some(state) = stack.pop() {
+            if reaching.insert(state) {
+                stack.extend(backward.get(&state).into_iter().flatten());
+            }
+        }
+        let all: BTreeSet<S> = starts
+            .iter()
+            .chain(ends.iter())
+            .chain(self.maped.keys())
+            .chain(backward.keys())
+            .copied()
+            .collect();
+        let keep: BTreeSet<S> = reached.intersection(&reaching).copied().collect();
+        let live = |state: &S| keep.contains(state);
+        self.end_state.retain(live);
+        if let Some(look) = &mut self.look {
+            look.end_before_word.retain(live);
+            look.end_before_other.retain(live);
+        }
+        self.maped.retain(|from, map| {
+            map.retain(|_, to| live(to));
+            live(from) && !map.is_empty()
+        });
+        // the dead start states are not removed
+        let starts: BTreeSet<S> = starts.into_iter().filter(|state| !live(state)).collect();
+        all.len() - keep.len() - starts.len()
+    }
+    // where a match starts when it can not start at the input start, because `prev` is before it
+    #[inline]
+    pub fn start_after(&self, prev: &V) -> S {
+        self.start_after_word((self.word)(prev))
+    }
+    // whether a match can end at `state` when `next` is the symbol following it
+    #[inline]
+    pub fn is_end_before(&self, state: &S, next: &V) -> bool {
+        self.is_end_before_word(state, (self.word)(next))
+    }
+    #[inline]
+    fn start_after_word(&self, word: bool) -> S {
+        match &self.look {
+            Some(look) if word => look.start_after_word,
+            Some(look) => look.start_after_other,
+            None => self.start_state,
+        }
+    }
+    #[inline]
+    fn is_end_before_word(&self, state: &S, word: bool) -> bool {
+        match &self.look {
+            Some(look) if word => look.end_before_word.contains(state),
+            Some(look) => look.end_before_other.contains(state),
+            None => self.end_state.contains(state),
+        }
+    }
 }
 
-fn move_t<'a, S: 'a, V>(
-    nfa: &Nfa<S, V>,
-    set: &mut impl Iterator<Item = &'a S>,
-    path: &V,
-) -> BTreeSet<S>
+fn move_t<'a, S, V>(nfa: &Nfa<S, V>, set: impl Iterator<Item = &'a S>, path: &V) -> BTreeSet<S>
 where
-    S: Hash + Ord + Copy + Eq,
+    S: 'a + Hash + Ord + Copy + Eq,
     V: Hash + Ord + Copy + Eq,
 {
     let mut result = BTreeSet::new();
     set.map(|state| nfa.move_t(state, path)).for_each(|set| {
-        result.extend(set.into_iter());
+        result.extend(set);
     });
     result
 }
@@ -135,60 +239,730 @@ where
     }
 }
 
-impl<'a, S, V> From<&'a Nfa<S, V>> for Dfa<usize, V>
+/*
+ * the symbols every state moves to the same state are one class, the rows of the compressed Dfa
+ * are indexed by class, so a row has one edge per class where the Dfa has one per symbol. The
+ * symbols are the elementary labels of the edges, a lookup must be by one symbol then, and when
+ * the Dfa has look edges the word and non word symbols are never in one class.
+ */
+#[derive(Debug)]
+pub struct Classes<V>
 where
-    S: Hash + Ord + Eq + Copy + Default,
-    V: Hash + Ord + Eq + Copy,
+    V: Hash + Eq + Ord,
 {
-    fn from(nfa: &'a Nfa<S, V>) -> Self {
-        let init: Vec<S> = closure(nfa, &mut [nfa.start_state].iter())
-            .into_iter()
-            .collect();
-        // map will store the relationship between the new Graph Node and the index in path_map
-        let mut map: HashMap<Vec<S>, usize> = [(init.clone(), 0)].into();
-        let mut v = vec![(init, BTreeMap::new())];
-        let mut top = 0usize;
-        let mut store = Vec::new();
-        while top != v.len() {
-            let c = top;
-            top = v.len();
-            for (i, (new_state, path_map)) in v[c..].iter_mut().enumerate() {
-                let closure = closure(nfa, &mut new_state.iter());
-                for path in nfa.all_path.iter() {
-                    let new_set: Vec<S> = closure
-                        .iter()
-                        .copied()
-                        .chain(move_t(nfa, &mut closure.iter(), path).into_iter())
-                        .collect();
-                    if !new_set.is_empty() {
-                        if !map.contains_key(&new_set) {
-                            map.insert(new_set.clone(), i + c + 1);
-                            store.push((new_set.clone(), Default::default()));
-                        }
-                        path_map.insert(path, new_set);
+    map: BTreeMap<V, usize>,
+    len: usize,
+}
+
+impl<V> Classes<V>
+where
+    V: Hash + Eq + Ord,
+{
+    #[inline]
+    pub fn get(&self, v: &V) -> Option<usize> {
+        self.map.get(v).copied()
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[derive(Debug)]
+pub struct ClassDfa<S, V>
+where
+    S: Hash + Eq + Ord,
+    V: Hash + Eq + Ord,
+{
+    classes: Classes<V>,
+    dfa: Dfa<S, usize>,
+    // the one of the Dfa, the classes are no word symbols themselves
+    word: fn(&V) -> bool,
+}
+
+impl<S, V> ClassDfa<S, V>
+where
+    S: Hash + Eq + Ord + Copy,
+    V: Hash + Eq + Ord + Copy,
+{
+    #[inline]
+    pub fn classes(&self) -> &Classes<V> {
+        &self.classes
+    }
+    // the same Dfa reading the class of each symbol
+    #[inline]
+    pub fn dfa(&self) -> &Dfa<S, usize> {
+        &self.dfa
+    }
+}
+
+impl<S, V> Dfa<S, V>
+where
+    S: Hash + Eq + Ord + Copy,
+    V: Hash + Eq + Ord + Copy + Partition,
+{
+    pub fn compress(&self) -> ClassDfa<S, V> {
+        let labels: Vec<V> = self.edges().map(|(_, v, _)| v).collect();
+        let states: Vec<S> = self.maped.keys().copied().collect();
+        // a class is known by where it moves every state, and whether it is made of word symbols
+        let mut signatures = BTreeMap::new();
+        let mut map = BTreeMap::new();
+        let mut representatives = Vec::new();
+        for v in V::partition(&labels) {
+            let targets: Vec<Option<S>> = states
+                .iter()
+                .map(|state| self.next_state(state, &v.bounds().0))
+                .collect();
+            let word = self.look.is_some() && (self.word)(&v);
+            let class = *signatures.entry((word, targets)).or_insert_with(|| {
+                representatives.push(v);
+                representatives.len() - 1
+            });
+            map.insert(v, class);
+        }
+        let mut dfa = Dfa::new(self.start_state);
+        dfa.end_state = self.end_state.clone();
+        dfa.look = self.look.clone();
+        for state in states.iter() {
+            for (class, v) in representatives.iter().enumerate() {
+                if let Some(to) = self.next_state(state, &v.bounds().0) {
+                    dfa.add_edges(*state, class, to).unwrap();
+                }
+            }
+        }
+        ClassDfa {
+            classes: Classes {
+                map,
+                len: representatives.len(),
+            },
+            dfa,
+            word: self.word,
+        }
+    }
+}
+
+// the operations reading the labels piece by piece, see Partition
+impl<S, V> Dfa<S, V>
+where
+    S: Hash + Eq + Ord + Copy,
+    V: Hash + Eq + Ord + Copy + Partition,
+{
+    // the strings accepted by both, without any limit, see DfaBuilder::intersection
+    #[inline]
+    pub fn intersection<T>(
+        &self,
+        other: &Dfa<T, V>,
+        alphabet: &[V],
+    ) -> Result<Dfa<usize, V>, DfaError>
+    where
+        T: Hash + Eq + Ord + Copy,
+    {
+        DfaBuilder::new().intersection(self, other, alphabet)
+    }
+    // the strings over `alphabet` not accepted, without any limit, see DfaBuilder::complement
+    #[inline]
+    pub fn complement(&self, alphabet: &[V]) -> Result<Dfa<usize, V>, DfaError> {
+        DfaBuilder::new().complement(self, alphabet)
+    }
+}
+
+/*
+ * the Nfa of the reversed strings, every edge turned around from the end states to the start
+ * state, two edges of one symbol may now leave the same state so it is not deterministic
+ * anymore.
+ *
+ * the look table is turned into look edges, which needs a few new states numbered after the
+ * last one: a start going by `^` into the end states and by `\b` or `\B` into copies of the states
+ * accepting before a word or another symbol, depending on the first symbol the copy reads, and
+ * an end reached by `$` from the start state and by `\b` or `\B` from copies of the look starts,
+ * depending on the last symbol read. Another symbol is only told from the input edge by `^` and
+ * `$`, so `\b` and `\B` let the edge pass for it, which accepts nothing more since the input
+ * edge passes wherever another symbol does. An empty match is only kept at the input edges, the
+ * looks cannot tell a word on both sides from another symbol on both sides.
+ */
+impl<S, V> Dfa<S, V>
+where
+    S: Hash + Eq + Ord + Copy + Step,
+    V: Hash + Eq + Ord + Copy,
+{
+    pub fn reverse(&self) -> Nfa<S, V> {
+        let Some(look) = &self.look else {
+            let mut nfa = Nfa::with_starts(self.end_state.iter().copied());
+            nfa.add_states(self.start_state);
+            nfa.add_end_state(self.start_state);
+            for state in self.end_state.iter() {
+                nfa.add_states(*state);
+            }
+            for (from, v, to) in self.edges() {
+                nfa.add_states(from);
+                nfa.add_states(to);
+                nfa.add_edges(to, Some(v), from);
+            }
+            return nfa;
+        };
+        let mut states: BTreeSet<S> = [
+            self.start_state,
+            look.start_after_word,
+            look.start_after_other,
+        ]
+        .into();
+        states.extend(self.end_state.iter().copied());
+        states.extend(look.end_before_word.iter().copied());
+        states.extend(look.end_before_other.iter().copied());
+        // the edges into each state, turned around they leave it
+        let mut into: BTreeMap<S, Vec<(V, S)>> = BTreeMap::new();
+        for (from, v, to) in self.edges() {
+            states.extend([from, to]);
+            into.entry(to).or_default().push((v, from));
+        }
+        let mut last = *states.last().unwrap();
+        let mut fresh = || {
+            last = last.next().expect("a state after the last one");
+            last
+        };
+        let (start, end) = (fresh(), fresh());
+        let mut nfa = Nfa::new(start);
+        for state in states.iter().chain([&start, &end]) {
+            nfa.add_states(*state);
+        }
+        nfa.add_end_state(end);
+        for (to, edges) in into.iter() {
+            for (v, from) in edges {
+                nfa.add_edges(*to, Some(*v), *from);
+            }
+        }
+        let word = self.word;
+        let context = |v: &V| {
+            if word(v) {
+                Context::Word
+            } else {
+                Context::Other
+            }
+        };
+        // the look between a symbol and a context, known to be a word or not
+        let boundary = |a: Context, b: Context| {
+            if a == b {
+                Look::NotWordBoundary
+            } else {
+                Look::WordBoundary
+            }
+        };
+        for state in self.end_state.iter() {
+            nfa.add_look(start, Look::End.reversed(), *state);
+        }
+        nfa.add_look(self.start_state, Look::Start.reversed(), end);
+        // a copy of a look start entered only by the symbols of one context
+        let mut entered: BTreeMap<(S, Context), S> = BTreeMap::new();
+        for (begin, before) in [
+            (look.start_after_word, Context::Word),
+            (look.start_after_other, Context::Other),
+        ] {
+            for (v, to) in self.maped.get(&begin).into_iter().flatten() {
+                let read = context(v);
+                let copy = *entered.entry((begin, read)).or_insert_with(|| {
+                    let copy = fresh();
+                    nfa.add_states(copy);
+                    copy
+                });
+                nfa.add_look(copy, boundary(before, read), end);
+                nfa.add_edges(*to, Some(*v), copy);
+            }
+        }
+        // a copy of an end state reading only the symbols of one context, into the copies above too
+        let mut leaving: BTreeMap<(S, Context), S> = BTreeMap::new();
+        for (ends, after) in [
+            (&look.end_before_word, Context::Word),
+            (&look.end_before_other, Context::Other),
+        ] {
+            for state in ends {
+                for (v, from) in into.get(state).into_iter().flatten() {
+                    let first = context(v);
+                    let copy = *leaving.entry((*state, first)).or_insert_with(|| {
+                        let copy = fresh();
+                        nfa.add_states(copy);
+                        copy
+                    });
+                    nfa.add_look(start, boundary(after, first), copy);
+                    nfa.add_edges(copy, Some(*v), *from);
+                    if let Some(to) = entered.get(&(*from, first)) {
+                        nfa.add_edges(copy, Some(*v), *to);
                     }
                 }
             }
-            v.append(&mut store);
         }
-        /*
-         * Here comes a question,if the DFA have multiple start state,then the DFA will become much
-         * slower then we want O(|s|) -> O(|r * S|).
-         */
-        let mut dfa = Dfa::new(Default::default());
-        for (state, path_map) in v {
-            let index = map.get(&state).unwrap();
-            for i in state {
-                if nfa.is_end(&i) {
-                    dfa.add_end_state(*index);
+        nfa
+    }
+}
+
+/*
+ * Hopcroft minimization. The Dfa is first completed by a dead state, None below, where every
+ * missing edge goes, then the states are split, starting from the accepting and the other ones,
+ * until the states of a block move by every symbol into one block: the blocks are the states of
+ * the minimal Dfa, unique up to the names of its states. The block of the dead state is dropped
+ * again, with the edges into it, so the result is as partial as the Dfa was.
+ *
+ * a block is split by the states moving into a smaller half of another split block, so each
+ * state is looked at O(log n) times per symbol. The symbols are the elementary labels, a block
+ * keeps the name and the labels of one of its states, the start state when it is in it.
+ *
+ * the states the start states do not reach are dropped first. With look edges the accepting
+ * states are told apart by the next symbols they accept before too.
+ */
+impl<S, V> Dfa<S, V>
+where
+    S: Hash + Eq + Ord + Copy,
+    V: Hash + Eq + Ord + Copy + Partition,
+{
+    pub fn optimize(&mut self) {
+        let mut starts = vec![self.start_state];
+        if let Some(look) = &self.look {
+            starts.extend([look.start_after_word, look.start_after_other]);
+        }
+        // the reachable states, then the dead state
+        let mut states: Vec<Option<S>> = Vec::new();
+        let mut index: HashMap<S, usize> = HashMap::new();
+        let mut stack = starts.clone();
+        while let Some(state) = stack.pop() {
+            if let Entry::Vacant(entry) = index.entry(state) {
+                entry.insert(states.len());
+                states.push(Some(state));
+                stack.extend(
+                    self.maped
+                        .get(&state)
+                        .into_iter()
+                        .flat_map(|map| map.values()),
+                );
+            }
+        }
+        let dead = states.len();
+        states.push(None);
+        let labels: Vec<V> = self.edges().map(|(_, v, _)| v).collect();
+        let symbols = V::partition(&labels);
+        // the states moving into each state by each symbol
+        let mut into = vec![vec![Vec::new(); states.len()]; symbols.len()];
+        for (i, state) in states.iter().enumerate() {
+            for (a, v) in symbols.iter().enumerate() {
+                let to = state
+                    .and_then(|state| self.next_state(&state, &v.bounds().0))
+                    .map_or(dead, |to| index[&to]);
+                into[a][to].push(i);
+            }
+        }
+        let accepts = |state: &Option<S>| match (state, &self.look) {
+            (None, _) => (false, false, false),
+            (Some(state), None) => (self.end_state.contains(state), false, false),
+            (Some(state), Some(look)) => (
+                self.end_state.contains(state),
+                look.end_before_word.contains(state),
+                look.end_before_other.contains(state),
+            ),
+        };
+        let mut blocks: Vec<Vec<usize>> = Vec::new();
+        let mut block_of = vec![0; states.len()];
+        let mut kinds = BTreeMap::new();
+        for (i, state) in states.iter().enumerate() {
+            let block = *kinds.entry(accepts(state)).or_insert_with(|| {
+                blocks.push(Vec::new());
+                blocks.len() - 1
+            });
+            blocks[block].push(i);
+            block_of[i] = block;
+        }
+        // every block but the largest one splits the others
+        let largest = (0..blocks.len()).max_by_key(|b| blocks[*b].len()).unwrap();
+        let mut waiting: Vec<usize> = (0..blocks.len()).filter(|b| *b != largest).collect();
+        let mut is_waiting = vec![false; blocks.len()];
+        for b in waiting.iter() {
+            is_waiting[*b] = true;
+        }
+        let mut marked = vec![false; states.len()];
+        while let Some(splitter) = waiting.pop() {
+            is_waiting[splitter] = false;
+            // the splitter as it is now, even if it gets split by itself below
+            let splitter = blocks[splitter].clone();
+            for into in into.iter() {
+                let mut touched: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+                for &j in splitter.iter() {
+                    for &i in into[j].iter() {
+                        touched.entry(block_of[i]).or_default().push(i);
+                    }
+                }
+                for (b, part) in touched {
+                    if part.len() == blocks[b].len() {
+                        continue;
+                    }
+                    for &i in part.iter() {
+                        marked[i] = true;
+                    }
+                    blocks[b].retain(|i| !marked[*i]);
+                    let new = blocks.len();
+                    for &i in part.iter() {
+                        marked[i] = false;
+                        block_of[i] = new;
+                    }
+                    blocks.push(part);
+                    is_waiting.push(false);
+                    let pick = if is_waiting[b] || blocks[new].len() < blocks[b].len() {
+                        new
+                    } else {
+                        b
+                    };
+                    waiting.push(pick);
+                    is_waiting[pick] = true;
                 }
             }
-            for (key, value) in path_map {
-                dfa.add_edges(*index, *key, *map.get(&value).unwrap())
-                    .unwrap();
+        }
+        // a block is named after its first start state, or else its lowest state
+        let dead_block = block_of[dead];
+        let mut names: Vec<Option<S>> = vec![None; blocks.len()];
+        for (b, block) in blocks.iter().enumerate() {
+            if b != dead_block {
+                names[b] = block.iter().filter_map(|i| states[*i]).min();
             }
         }
-        dfa
+        for start in starts.iter().rev() {
+            let b = block_of[index[start]];
+            if b != dead_block {
+                names[b] = Some(*start);
+            }
+        }
+        let name = |state: &S| names[block_of[index[state]]];
+        let mut maped = BTreeMap::new();
+        for named in names.iter().flatten() {
+            if let Some(map) = self.maped.get(named) {
+                let map: BTreeMap<V, S> = map
+                    .iter()
+                    .filter_map(|(v, to)| Some((*v, name(to)?)))
+                    .collect();
+                if !map.is_empty() {
+                    maped.insert(*named, map);
+                }
+            }
+        }
+        self.maped = maped;
+        let kept = |state: &S| index.contains_key(state) && name(state) == Some(*state);
+        self.end_state.retain(kept);
+        if let Some(look) = &mut self.look {
+            // a look start in the dead block stays as it is, without any edge
+            look.start_after_word = name(&look.start_after_word).unwrap_or(look.start_after_word);
+            look.start_after_other =
+                name(&look.start_after_other).unwrap_or(look.start_after_other);
+            look.end_before_word.retain(kept);
+            look.end_before_other.retain(kept);
+        }
+    }
+}
+
+/*
+ * the limits of the subset construction, which can make exponentially many states out of a Nfa,
+ * and of the product of two Dfa, which can make as many as both have multiplied. The memory
+ * counted is the sets of states kept to find the Dfa states again and the edges, not the whole
+ * heap, so keep a margin. The cancel flag is checked before each state is expanded,
+ * another thread setting it stops the construction with DfaError::Cancelled.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct DfaBuilder {
+    max_states: Option<usize>,
+    max_memory_bytes: Option<usize>,
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+impl DfaBuilder {
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+    // more states fail with DfaError::TooLarge
+    #[inline]
+    pub fn max_states(mut self, limit: usize) -> Self {
+        self.max_states = Some(limit);
+        self
+    }
+    #[inline]
+    pub fn max_memory_bytes(mut self, limit: usize) -> Self {
+        self.max_memory_bytes = Some(limit);
+        self
+    }
+    #[inline]
+    pub fn cancel(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(flag);
+        self
+    }
+    #[inline]
+    fn cancelled(&self) -> Result<(), DfaError> {
+        match &self.cancel {
+            Some(flag) if flag.load(Ordering::Relaxed) => Err(DfaError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+    // whether one more state fits after the `states` there are
+    #[inline]
+    fn room(&self, states: usize) -> Result<(), DfaError> {
+        match self.max_states {
+            Some(limit) if states >= limit => Err(DfaError::TooLarge),
+            _ => Ok(()),
+        }
+    }
+    #[inline]
+    fn within(&self, memory: usize) -> Result<(), DfaError> {
+        match self.max_memory_bytes {
+            Some(limit) if memory > limit => Err(DfaError::TooLarge),
+            _ => Ok(()),
+        }
+    }
+    /*
+     * both operations below walk the Dfa piece by piece of `alphabet`, disjoint pieces such that
+     * every label is made of whole pieces of it, then a lookup by the first symbol of a piece
+     * finds the label it belongs to. The states of the result are numbered from 0 in the order
+     * they are found, and counted against the limits like the subset construction does.
+     */
+    // the strings accepted by both, by the product of the two Dfa
+    pub fn intersection<S, T, V>(
+        &self,
+        dfa: &Dfa<S, V>,
+        other: &Dfa<T, V>,
+        alphabet: &[V],
+    ) -> Result<Dfa<usize, V>, DfaError>
+    where
+        S: Hash + Eq + Ord + Copy,
+        T: Hash + Eq + Ord + Copy,
+        V: Hash + Eq + Ord + Copy + Partition,
+    {
+        if dfa.look.is_some() || other.look.is_some() {
+            return Err(DfaError::HasLooks);
+        }
+        self.room(0)?;
+        let start = (dfa.start_state, other.start_state);
+        // a pair is both a key of the map and an item of the queue
+        let state_bytes = 2 * size_of::<((S, T), usize)>();
+        let mut memory = state_bytes;
+        let mut map = HashMap::from([(start, 0)]);
+        let mut queue = vec![start];
+        let mut result = Dfa::new(0);
+        let mut top = 0;
+        while top < queue.len() {
+            self.cancelled()?;
+            let (p, q) = queue[top];
+            if dfa.end_state.contains(&p) && other.end_state.contains(&q) {
+                result.add_end_state(top);
+            }
+            for v in alphabet {
+                let first = v.bounds().0;
+                let next = match (dfa.next_state(&p, &first), other.next_state(&q, &first)) {
+                    (Some(p), Some(q)) => (p, q),
+                    _ => continue,
+                };
+                let to = match map.get(&next) {
+                    Some(to) => *to,
+                    None => {
+                        self.room(queue.len())?;
+                        memory += state_bytes;
+                        map.insert(next, queue.len());
+                        queue.push(next);
+                        queue.len() - 1
+                    }
+                };
+                memory += size_of::<(V, usize)>();
+                self.within(memory)?;
+                result.add_edges(top, *v, to)?;
+            }
+            top += 1;
+        }
+        Ok(result)
+    }
+    /*
+     * the strings over `alphabet` not accepted, every missing edge goes to a new dead state first,
+     * so the Dfa is complete, then accepting and non accepting states are swapped
+     */
+    pub fn complement<S, V>(
+        &self,
+        dfa: &Dfa<S, V>,
+        alphabet: &[V],
+    ) -> Result<Dfa<usize, V>, DfaError>
+    where
+        S: Hash + Eq + Ord + Copy,
+        V: Hash + Eq + Ord + Copy + Partition,
+    {
+        if dfa.look.is_some() {
+            return Err(DfaError::HasLooks);
+        }
+        self.room(0)?;
+        // None is the dead state
+        let start = Some(dfa.start_state);
+        let state_bytes = 2 * size_of::<(Option<S>, usize)>();
+        let mut memory = state_bytes;
+        let mut map = HashMap::from([(start, 0)]);
+        let mut queue = vec![start];
+        let mut result = Dfa::new(0);
+        let mut top = 0;
+        while top < queue.len() {
+            self.cancelled()?;
+            let state = queue[top];
+            if !state.is_some_and(|state| dfa.end_state.contains(&state)) {
+                result.add_end_state(top);
+            }
+            for v in alphabet {
+                let next = state.and_then(|state| dfa.next_state(&state, &v.bounds().0));
+                let to = match map.get(&next) {
+                    Some(to) => *to,
+                    None => {
+                        self.room(queue.len())?;
+                        memory += state_bytes;
+                        map.insert(next, queue.len());
+                        queue.push(next);
+                        queue.len() - 1
+                    }
+                };
+                memory += size_of::<(V, usize)>();
+                self.within(memory)?;
+                result.add_edges(top, *v, to)?;
+            }
+            top += 1;
+        }
+        Ok(result)
+    }
+    /*
+     * subset construction, a state of the Dfa is a set of states of the Nfa, and, when the Nfa
+     * has look edges, the context of the symbol before it. Without look edges the sets are closed
+     * right away, with them the closure needs the next symbol, so the sets are kept open and
+     * closed once per symbol.
+     *
+     * the sets are moved piece by piece of Nfa::pieces, so overlapping labels of different
+     * states read the same pieces, and a piece is looked up by its first symbol in the labels
+     * of a state, which stay disjoint.
+     */
+    pub fn build_with_looks<S, V>(&self, nfa: &Nfa<S, V>) -> Result<Dfa<usize, V>, DfaError>
+    where
+        S: Hash + Ord + Eq + Copy,
+        V: Hash + Ord + Eq + Copy + WordSymbol + Partition,
+    {
+        self.subset(nfa, V::is_word)
+    }
+    // the same for a Nfa without look edges, for any symbol type, DfaError::HasLooks otherwise
+    pub fn build<S, V>(&self, nfa: &Nfa<S, V>) -> Result<Dfa<usize, V>, DfaError>
+    where
+        S: Hash + Ord + Eq + Copy,
+        V: Hash + Ord + Eq + Copy + Partition,
+    {
+        if nfa.has_looks() {
+            return Err(DfaError::HasLooks);
+        }
+        self.subset(nfa, |_| false)
+    }
+    fn subset<S, V>(&self, nfa: &Nfa<S, V>, word: fn(&V) -> bool) -> Result<Dfa<usize, V>, DfaError>
+    where
+        S: Hash + Ord + Eq + Copy,
+        V: Hash + Ord + Eq + Copy + Partition,
+    {
+        let looks = nfa.has_looks();
+        let pieces = nfa.pieces();
+        let open = |set: BTreeSet<S>| -> Vec<S> {
+            if looks {
+                set.into_iter().collect()
+            } else {
+                nfa.closure_around(set, Context::Edge, Context::Edge)
+                    .into_iter()
+                    .collect()
+            }
+        };
+        let befores: &[Context] = if looks {
+            &[Context::Edge, Context::Word, Context::Other]
+        } else {
+            &[Context::Edge]
+        };
+        // a set is both a key of the map and an item of the queue
+        let state_bytes = |set: &[S]| 2 * (size_of_val(set) + size_of::<(Vec<S>, Context)>());
+        let mut memory = 0;
+        // map will store the relationship between the new Graph Node and its index in queue
+        let mut map: HashMap<(Vec<S>, Context), usize> = HashMap::new();
+        let mut queue = Vec::new();
+        for before in befores {
+            self.room(queue.len())?;
+            let key = (open(nfa.start_states.clone()), *before);
+            memory += state_bytes(&key.0);
+            map.insert(key.clone(), queue.len());
+            queue.push(key);
+        }
+        let mut dfa = Dfa::new(0);
+        let mut end_before_word = HashSet::new();
+        let mut end_before_other = HashSet::new();
+        let mut top = 0;
+        while top < queue.len() {
+            self.cancelled()?;
+            let (set, before) = queue[top].clone();
+            let accepts = |after| {
+                nfa.closure_around(set.iter().copied(), before, after)
+                    .iter()
+                    .any(|state| nfa.is_end(state))
+            };
+            if accepts(Context::Edge) {
+                dfa.add_end_state(top);
+            }
+            if looks && accepts(Context::Word) {
+                end_before_word.insert(top);
+            }
+            if looks && accepts(Context::Other) {
+                end_before_other.insert(top);
+            }
+            for path in pieces.iter() {
+                let first = path.bounds().0;
+                let after = if word(&first) {
+                    Context::Word
+                } else {
+                    Context::Other
+                };
+                let closure = nfa.closure_around(set.iter().copied(), before, after);
+                let moved = move_t(nfa, closure.iter(), &first);
+                if moved.is_empty() {
+                    continue;
+                }
+                let key = (open(moved), if looks { after } else { Context::Edge });
+                let to = match map.get(&key) {
+                    Some(to) => *to,
+                    None => {
+                        self.room(queue.len())?;
+                        memory += state_bytes(&key.0);
+                        map.insert(key.clone(), queue.len());
+                        queue.push(key);
+                        queue.len() - 1
+                    }
+                };
+                memory += size_of::<(V, usize)>();
+                self.within(memory)?;
+                dfa.add_edges(top, *path, to)?;
+            }
+            top += 1;
+        }
+        if looks {
+            dfa.word = word;
+            dfa.look = Some(LookTable {
+                start_after_word: 1,
+                start_after_other: 2,
+                end_before_word,
+                end_before_other,
+            });
+        }
+        Ok(dfa)
+    }
+}
+
+// the subset construction without any limit, look edges included, see DfaBuilder::build_with_looks
+impl<'a, S, V> From<&'a Nfa<S, V>> for Dfa<usize, V>
+where
+    S: Hash + Ord + Eq + Copy,
+    V: Hash + Ord + Eq + Copy + WordSymbol + Partition,
+{
+    #[inline]
+    fn from(nfa: &'a Nfa<S, V>) -> Self {
+        match DfaBuilder::new().build_with_looks(nfa) {
+            Ok(dfa) => dfa,
+            // nothing to exceed, nothing to cancel, and the pieces never share a symbol
+            Err(_) => unreachable!(),
+        }
     }
 }
 
@@ -214,43 +988,127 @@ where
     }
 }
 
+impl<S, V> StateMachine for ClassDfa<S, V>
+where
+    S: Hash + Eq + Ord + Copy,
+    V: Hash + Eq + Ord + Copy,
+{
+    type State = S;
+
+    type V = V;
+
+    type NextState = S;
+
+    fn is_end(&self, state: &Self::State) -> bool {
+        self.dfa.is_end(state)
+    }
+
+    fn next_state(&self, from: &Self::State, path: &Self::V) -> Option<Self::NextState> {
+        self.classes
+            .get(path)
+            .and_then(|class| self.dfa.next_state(from, &class))
+    }
+}
+
+// what the matcher needs besides the edges, the Dfa and its compressed form both have it
+trait Restart<V>: StateMachine<V = V> {
+    fn start(&self) -> Self::State;
+    fn start_after(&self, prev: &V) -> Self::State;
+    fn is_end_before(&self, state: &Self::State, next: &V) -> bool;
+}
+
+impl<S, V> Restart<V> for Dfa<S, V>
+where
+    S: Hash + Eq + Ord + Copy,
+    V: Hash + Eq + Ord + Copy,
+{
+    #[inline]
+    fn start(&self) -> S {
+        self.start_state
+    }
+    #[inline]
+    fn start_after(&self, prev: &V) -> S {
+        Dfa::start_after(self, prev)
+    }
+    #[inline]
+    fn is_end_before(&self, state: &S, next: &V) -> bool {
+        Dfa::is_end_before(self, state, next)
+    }
+}
+
+impl<S, V> Restart<V> for ClassDfa<S, V>
+where
+    S: Hash + Eq + Ord + Copy,
+    V: Hash + Eq + Ord + Copy,
+{
+    #[inline]
+    fn start(&self) -> S {
+        self.dfa.start_state
+    }
+    #[inline]
+    fn start_after(&self, prev: &V) -> S {
+        self.dfa.start_after_word((self.word)(prev))
+    }
+    #[inline]
+    fn is_end_before(&self, state: &S, next: &V) -> bool {
+        self.dfa.is_end_before_word(state, (self.word)(next))
+    }
+}
+
+/*
+ * the iterator is taken as the whole input, a `^` only matches before its first symbol and a `$`
+ * only after its last one. Symbols no match can start with are skipped.
+ */
 impl<S, V, I> Matcher<I> for Dfa<S, V>
 where
     S: Hash + Eq + Ord + Copy + Debug,
     V: Hash + Eq + Ord + Copy + Debug,
     I: Iterator<Item = V>,
-    Self: StateMachine<NextState = S, State = S, V = V>,
 {
     type Matched = Vec<V>;
 
+    #[inline]
     fn r#match(&self, iter: &mut I) -> Option<Self::Matched> {
-        let mut state = self.start_state;
-        let mut result = if let (_, Some(l)) = iter.size_hint() {
-            Vec::with_capacity(l)
-        } else {
-            Vec::with_capacity(iter.size_hint().0)
-        };
-        for i in &mut *iter {
-            match self.next_state(&state, &i) {
-                Some(next_state) => {
-                    result.push(i);
-                    state = next_state;
-                }
-                None => {
-                    if result.is_empty() {
-                        continue;
-                    } else {
-                        return if self.is_end(&state) {
-                            Some(result)
-                        } else {
-                            None
-                        };
-                    }
-                }
+        walk(self, iter)
+    }
+}
+
+impl<S, V, I> Matcher<I> for ClassDfa<S, V>
+where
+    S: Hash + Eq + Ord + Copy + Debug,
+    V: Hash + Eq + Ord + Copy + Debug,
+    I: Iterator<Item = V>,
+{
+    type Matched = Vec<V>;
+
+    #[inline]
+    fn r#match(&self, iter: &mut I) -> Option<Vec<V>> {
+        walk(self, iter)
+    }
+}
+
+fn walk<M, V, I>(machine: &M, iter: &mut I) -> Option<Vec<V>>
+where
+    M: Restart<V, NextState = <M as StateMachine>::State>,
+    I: Iterator<Item = V>,
+{
+    let mut state = machine.start();
+    let mut result = if let (_, Some(l)) = iter.size_hint() {
+        Vec::with_capacity(l)
+    } else {
+        Vec::with_capacity(iter.size_hint().0)
+    };
+    for i in &mut *iter {
+        match machine.next_state(&state, &i) {
+            Some(next_state) => {
+                result.push(i);
+                state = next_state;
             }
+            None if result.is_empty() => state = machine.start_after(&i),
+            None => return machine.is_end_before(&state, &i).then_some(result),
         }
-        None
     }
+    (!result.is_empty() && machine.is_end(&state)).then_some(result)
 }
 
 #[macro_export]
@@ -273,8 +1131,14 @@ macro_rules! Dfa {
 
 #[cfg(test)]
 mod test_dfa {
-    use super::{super::Action};
-    use crate::{matches::Matcher, automate::Nfa};
+    use super::super::{Action, Look, Nfa, StateMachine};
+    use super::{Dfa, DfaBuilder, DfaError};
+    use crate::matches::{Input, Matcher};
+    use std::collections::BTreeSet;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
     #[test]
     fn dfa_macro() {
         let dfa = Dfa! {
@@ -292,8 +1156,421 @@ mod test_dfa {
             dfa.matches(&mut iter).collect::<Vec<Vec<Action<char>>>>(),
             Vec::<Vec<Action<char>>>::new()
         );
+        let mut iter = "bd1!".chars().map(Action::Single);
+        assert_eq!(
+            dfa.matches(&mut iter).collect::<Vec<Vec<Action<char>>>>(),
+            vec![vec![
+                Action::Single('b'),
+                Action::Single('d'),
+                Action::Single('1')
+            ]]
+        );
     }
     #[test]
     fn determine_nfa() {
+        // two pieces side by side, `ab*` from 0 and `b` then `c` from 2, without a common start
+        let nfa = crate::Nfa! {
+            Start:[0, 2],
+            End:[1, 4],
+            V: {
+                0 => (b'a') => 1,
+                1 => (b'b') => 1,
+                2 => (b'b') => 3,
+                3 => () => 5,
+                5 => (b'c') => 4,
+            }
+        };
+        let dfa = Dfa::from(&nfa);
+        let accepts = |s: &[u8]| {
+            s.iter()
+                .try_fold(dfa.start_state(), |state, v| dfa.next_state(&state, v))
+                .is_some_and(|state| dfa.is_end(&state))
+        };
+        assert!(accepts(b"a") && accepts(b"abb") && accepts(b"bc"));
+        assert!(!accepts(b"") && !accepts(b"b") && !accepts(b"ac") && !accepts(b"abc"));
+        // any symbol type through the builder, the word symbols only matter with look edges
+        let mut nfa: Nfa<usize, u32> = Nfa::new(0);
+        nfa.add_edges(0, Some(7), 1);
+        nfa.add_edges(1, Some(1 << 20), 1);
+        nfa.add_end_state(1);
+        let dfa = DfaBuilder::new().build(&nfa).unwrap();
+        let mut iter = [3, 7, 1 << 20, 5].into_iter();
+        assert_eq!(dfa.r#match(&mut iter), Some(vec![7, 1 << 20]));
+    }
+    #[test]
+    fn intersection_and_complement() {
+        // words of a and b with an even number of a, and those ending with b
+        let even = Dfa! {
+            Start:0,
+            End:[0],
+            V: {
+                0 => (b'a') => 1,
+                0 => (b'b') => 0,
+                1 => (b'a') => 0,
+                1 => (b'b') => 1,
+            }
+        };
+        let ends_b = Dfa! {
+            Start:0,
+            End:[1],
+            V: {
+                0 => (b'a') => 0,
+                0 => (b'b') => 1,
+                1 => (b'a') => 0,
+                1 => (b'b') => 1,
+            }
+        };
+        let accepts = |dfa: &Dfa<usize, u8>, s: &[u8]| {
+            let mut state = dfa.start_state();
+            for v in s {
+                match dfa.next_state(&state, v) {
+                    Some(next) => state = next,
+                    None => return false,
+                }
+            }
+            dfa.is_end(&state)
+        };
+        let alphabet = [b'a', b'b', b'c'];
+        let both = even.intersection(&ends_b, &alphabet).unwrap();
+        assert!(accepts(&both, b"aab"));
+        assert!(accepts(&both, b"b"));
+        assert!(!accepts(&both, b"ab"));
+        assert!(!accepts(&both, b"aa"));
+        let odd = even.complement(&alphabet).unwrap();
+        assert!(accepts(&odd, b"ab"));
+        assert!(!accepts(&odd, b"aab"));
+        // the c has no edge in `even`, it leads to the dead state which is accepting now
+        assert!(accepts(&odd, b"aac"));
+        assert!(accepts(&odd, b"cab"));
+        assert!(!accepts(&odd.complement(&alphabet).unwrap(), b"cab"));
+        assert!(accepts(&odd.complement(&alphabet).unwrap(), b""));
+    }
+    // every state named by the Dfa
+    fn states<V: std::hash::Hash + Ord + Copy>(dfa: &Dfa<usize, V>) -> BTreeSet<usize> {
+        let edges = dfa.edges().flat_map(|(from, _, to)| [from, to]);
+        std::iter::once(dfa.start_state())
+            .chain(dfa.end_state.iter().copied())
+            .chain(edges)
+            .collect()
+    }
+    // whether both accept the same strings over `symbols` up to `len` symbols
+    fn same_language<V: std::hash::Hash + Ord + Copy + std::fmt::Debug>(
+        a: &Dfa<usize, V>,
+        b: &Dfa<usize, V>,
+        symbols: &[V],
+        len: usize,
+    ) {
+        let accepts = |dfa: &Dfa<usize, V>, s: &[V]| {
+            s.iter()
+                .try_fold(dfa.start_state(), |state, v| dfa.next_state(&state, v))
+                .is_some_and(|state| dfa.is_end(&state))
+        };
+        let mut strings = vec![Vec::new()];
+        for _ in 0..=len {
+            for s in strings.iter() {
+                assert_eq!(accepts(a, s), accepts(b, s), "{:?}", s);
+            }
+            strings = strings
+                .iter()
+                .flat_map(|s| symbols.iter().map(move |v| [&s[..], &[*v]].concat()))
+                .collect();
+        }
+    }
+    #[test]
+    fn optimize() {
+        // (a|b)*abb, the textbook Dfa of 4 states
+        let nfa = crate::regex::parse("(a|b)*abb").unwrap();
+        let dfa = Dfa::from(&nfa);
+        let mut minimal = Dfa::from(&nfa);
+        minimal.optimize();
+        assert_eq!(states(&minimal).len(), 4);
+        assert!(states(&dfa).len() >= 4);
+        same_language(
+            &dfa,
+            &minimal,
+            &[Action::Single('a'), Action::Single('b')],
+            8,
+        );
+        // the same language from the Glushkov construction gives the same Dfa
+        let glushkov = crate::regex::RegexBuilder::new()
+            .construction(crate::regex::Construction::Glushkov)
+            .build("(a|b)*abb")
+            .unwrap();
+        let mut other = Dfa::from(&glushkov);
+        other.optimize();
+        assert_eq!(states(&other).len(), 4);
+        // 3 is unreachable, 4 and 5 reach no end, 1 and 2 are equivalent
+        let garbage = || {
+            Dfa! {
+                Start:0,
+                End:[1, 2],
+                V: {
+                    0 => (b'a') => 1,
+                    0 => (b'b') => 2,
+                    0 => (b'c') => 4,
+                    1 => (b'a') => 1,
+                    1 => (b'b') => 2,
+                    2 => (b'a') => 1,
+                    2 => (b'b') => 2,
+                    2 => (b'c') => 5,
+                    3 => (b'a') => 0,
+                    4 => (b'a') => 5,
+                    5 => (b'a') => 4,
+                }
+            }
+        };
+        let dfa = garbage();
+        let mut minimal = garbage();
+        minimal.optimize();
+        assert_eq!(states(&minimal), [0, 1].into());
+        assert_eq!(minimal.end_state, [1].into());
+        assert_eq!(minimal.edges().count(), 4);
+        same_language(&dfa, &minimal, b"abc", 6);
+        // once minimal it stays the same
+        let before: Vec<_> = minimal.edges().collect();
+        minimal.optimize();
+        assert_eq!(minimal.edges().collect::<Vec<_>>(), before);
+        // an empty language keeps its start state only
+        let mut empty = Dfa! {
+            Start:0,
+            End:[9],
+            V: {
+                0 => (b'a') => 1,
+                1 => (b'a') => 0,
+            }
+        };
+        empty.optimize();
+        assert_eq!(states(&empty), [0].into());
+        assert!(empty.end_state.is_empty());
+    }
+    #[test]
+    fn optimize_labels_and_looks() {
+        let chars: Vec<_> = "aginopz-".chars().map(Action::Single).collect();
+        for pattern in ["[a-z]*ing|p[a-o]ng", "(a|[a-z])*z|[^a]"] {
+            let dfa = Dfa::from(&crate::regex::parse(pattern).unwrap());
+            let mut minimal = Dfa::from(&crate::regex::parse(pattern).unwrap());
+            minimal.optimize();
+            assert!(states(&minimal).len() <= states(&dfa).len());
+            same_language(&dfa, &minimal, &chars, 5);
+        }
+        let pattern = r"\bfoo\b|^x$|o\Bo";
+        let dfa = Dfa::from(&crate::regex::parse(pattern).unwrap());
+        let mut minimal = Dfa::from(&crate::regex::parse(pattern).unwrap());
+        minimal.optimize();
+        assert!(states(&minimal).len() <= states(&dfa).len());
+        for input in [
+            "foo", "a foo", "foobar", "x", "ax", "xfoo", "food", "oo", "o o",
+        ] {
+            assert_eq!(
+                minimal.r#match(&mut input.chars().map(Action::Single)),
+                dfa.r#match(&mut input.chars().map(Action::Single)),
+                "{}",
+                input
+            );
+        }
+    }
+    #[test]
+    fn builder_limits() {
+        // (a|b)*a(a|b){7}, the Dfa remembers the last 8 symbols, 2^8 states at least
+        let nfa = crate::regex::parse("(a|b)*a(a|b){7}").unwrap();
+        let states = Dfa::from(&nfa).maped.len();
+        assert!(states >= 1 << 8);
+        let dfa = DfaBuilder::new()
+            .max_states(states)
+            .max_memory_bytes(1 << 30)
+            .build(&nfa)
+            .unwrap();
+        assert_eq!(dfa.maped.len(), states);
+        let too_large =
+            |builder: DfaBuilder| matches!(builder.build(&nfa), Err(DfaError::TooLarge));
+        assert!(too_large(DfaBuilder::new().max_states(states - 1)));
+        assert!(too_large(DfaBuilder::new().max_memory_bytes(1 << 12)));
+        let flag = Arc::new(AtomicBool::new(false));
+        let builder = DfaBuilder::new().cancel(flag.clone());
+        assert!(builder.build(&nfa).is_ok());
+        flag.store(true, Ordering::Relaxed);
+        assert!(matches!(builder.build(&nfa), Err(DfaError::Cancelled)));
+        // the start states count too, `^` has three of them and no other
+        let nfa = crate::regex::parse("^").unwrap();
+        let build = |limit| DfaBuilder::new().max_states(limit).build_with_looks(&nfa);
+        assert!(build(3).is_ok());
+        assert!(matches!(build(2), Err(DfaError::TooLarge)));
+        let dfa = Dfa::from(&crate::regex::parse("a").unwrap());
+        let alphabet = [Action::Range('\0', char::MAX)];
+        let builder = DfaBuilder::new().max_states(0);
+        assert!(matches!(
+            builder.build_with_looks(&nfa),
+            Err(DfaError::TooLarge)
+        ));
+        assert!(matches!(
+            builder.intersection(&dfa, &dfa, &alphabet),
+            Err(DfaError::TooLarge)
+        ));
+        assert!(matches!(
+            builder.complement(&dfa, &alphabet),
+            Err(DfaError::TooLarge)
+        ));
+    }
+    #[test]
+    fn trim() {
+        let mut dfa = Dfa! {
+            Start:0,
+            End:[1],
+            V: {
+                0 => (b'a') => 1,
+                0 => (b'b') => 2,
+                2 => (b'b') => 2,
+                3 => (b'a') => 1,
+            }
+        };
+        assert_eq!(dfa.trim(), 2);
+        assert_eq!(dfa.edges().collect::<Vec<_>>(), [(0, b'a', 1)]);
+        assert_eq!(dfa.trim(), 0);
+        // the start stays even when it reaches no end
+        let mut dfa = Dfa! {
+            Start:0,
+            End:[5],
+            V: {
+                0 => (b'a') => 1,
+            }
+        };
+        assert_eq!(dfa.trim(), 2);
+        assert_eq!(dfa.edges().count(), 0);
+        assert_eq!(dfa.start_state(), 0);
+    }
+    #[test]
+    fn reverse() {
+        // words of a and b ending with ab
+        let dfa = Dfa! {
+            Start:0,
+            End:[2],
+            V: {
+                0 => (b'a') => 1,
+                0 => (b'b') => 0,
+                1 => (b'a') => 1,
+                1 => (b'b') => 2,
+                2 => (b'a') => 1,
+                2 => (b'b') => 0,
+            }
+        };
+        let nfa = dfa.reverse();
+        let accepts = |s: &[u8]| {
+            let mut set = nfa.start_closure();
+            for v in s {
+                set = set.iter().flat_map(|state| nfa.move_t(state, v)).collect();
+            }
+            set.iter().any(|state| (&nfa).is_end(state))
+        };
+        assert!(accepts(b"ba") && accepts(b"baab") && accepts(b"babb"));
+        assert!(!accepts(b"ab") && !accepts(b"b") && !accepts(b""));
+        // the looks are carried over, the reversed Dfa finds what the reversed Nfa does
+        for pattern in [
+            r"\bfoo\b",
+            "^ab",
+            "ab$",
+            r"\Ba+",
+            r"a\b",
+            r"-\B.",
+            r"^\b-|x\B$",
+            r"\b[a-c-]+\B",
+            r"(?:\Bb|-\b)a",
+        ] {
+            let nfa = crate::regex::parse(pattern).unwrap();
+            let mut minimal = Dfa::from(&nfa);
+            minimal.optimize();
+            let reversed = Dfa::from(&nfa).reverse();
+            let minimal = minimal.reverse();
+            let nfa = nfa.reverse();
+            for input in [
+                "foo", "a foo b", "foobar", "(foo)", "xab", "abc", "aab", "a-b", "--x", "ba-a",
+                "-ab-",
+            ] {
+                let find = |nfa: &Nfa<_, _>| {
+                    nfa.find(&mut Input::new(input.chars().rev().map(Action::Single)))
+                };
+                assert_eq!(find(&reversed), find(&nfa), "{pattern} on {input}");
+                assert_eq!(find(&minimal), find(&nfa), "{pattern} on {input}");
+            }
+        }
+    }
+    #[test]
+    fn overlapping_labels() {
+        // [a-z]x|ey, the e of ey is also read by [a-z] from another state
+        let mut nfa = Nfa::new(0);
+        nfa.add_edges(0, None, 1);
+        nfa.add_edges(0, None, 3);
+        nfa.add_edges(1, Some(Action::Range('a', 'z')), 2);
+        nfa.add_edges(2, Some(Action::Single('x')), 5);
+        nfa.add_edges(3, Some(Action::Single('e')), 4);
+        nfa.add_edges(4, Some(Action::Single('y')), 5);
+        nfa.add_end_state(5);
+        let dfa = Dfa::from(&nfa);
+        let accepts = |s: &str| {
+            s.chars()
+                .try_fold(dfa.start_state(), |state, c| {
+                    dfa.next_state(&state, &Action::Single(c))
+                })
+                .is_some_and(|state| dfa.is_end(&state))
+        };
+        assert!(accepts("ax") && accepts("ex") && accepts("ey") && accepts("zx"));
+        assert!(!accepts("ay") && !accepts("e") && !accepts("exy"));
+    }
+    #[test]
+    fn compress() {
+        // identifiers, 26 edges out of the start and 36 out of the other state
+        let mut dfa = Dfa::new(0);
+        for v in (b'a'..=b'z').chain(b'0'..=b'9') {
+            if v.is_ascii_lowercase() {
+                dfa.add_edges(0, v, 1).unwrap();
+            }
+            dfa.add_edges(1, v, 1).unwrap();
+        }
+        dfa.add_end_state(1);
+        let compressed = dfa.compress();
+        assert_eq!(compressed.classes().len(), 2);
+        assert_eq!(compressed.dfa().edges().count(), 3);
+        assert_eq!(
+            compressed.classes().get(&b'q'),
+            compressed.classes().get(&b'a')
+        );
+        assert_eq!(compressed.classes().get(&b'-'), None);
+        for input in [&b"x1 = a2b"[..], b"9a", b"--", b""] {
+            assert_eq!(
+                compressed.r#match(&mut input.iter().copied()),
+                dfa.r#match(&mut input.iter().copied())
+            );
+        }
+        // overlapping chars classes, and a word boundary keeping word and non word chars apart
+        for pattern in ["[a-z]*ing|p[a-o]ng|[^a-z]", r"f[a-z!]+"] {
+            let dfa = Dfa::from(&crate::regex::parse(pattern).unwrap());
+            let compressed = dfa.compress();
+            assert!(compressed.dfa().edges().count() <= dfa.edges().count());
+            for input in ["ping pong!", "sing", "fo!o", "fun!", "a fun day", "pung"] {
+                assert_eq!(
+                    compressed.r#match(&mut input.chars().map(Action::Single)),
+                    dfa.r#match(&mut input.chars().map(Action::Single)),
+                    "{} on {:?}",
+                    pattern,
+                    input
+                );
+            }
+        }
+    }
+    #[test]
+    fn look_edges() {
+        // `$` then `a`, only matches before the input end
+        let mut nfa = Nfa::new(0);
+        nfa.add_edges(0, Some(Action::Single('a')), 1);
+        nfa.add_look(1, Look::End, 2);
+        nfa.add_end_state(2);
+        assert!(matches!(
+            DfaBuilder::new().build(&nfa),
+            Err(DfaError::HasLooks)
+        ));
+        let dfa = Dfa::from(&nfa);
+        let mut iter = "ba".chars().map(Action::Single);
+        assert_eq!(dfa.r#match(&mut iter), Some(vec![Action::Single('a')]));
+        let mut iter = "ab".chars().map(Action::Single);
+        assert_eq!(dfa.r#match(&mut iter), None);
     }
 }
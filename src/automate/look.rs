@@ -0,0 +1,132 @@
+use super::Action;
+
+/*
+ * zero width assertions, an edge labeled by a Look consumes nothing like an epsilon edge,
+ * but it can only be walked when the symbols around the current position satisfy it
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Look {
+    // `^`, the start of the input
+    Start,
+    // `$`, the end of the input
+    End,
+    // `\b`
+    WordBoundary,
+    // `\B`
+    NotWordBoundary,
+}
+
+// what is found on one side of a position in the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Context {
+    // no symbol at all, the position is the start or the end of the input
+    Edge,
+    Word,
+    Other,
+}
+
+/*
+ * the symbols able to tell a word boundary, a Range is expected to be all word or all non word,
+ * the regex construction splits its classes that way when the pattern contains `\b` or `\B`
+ */
+pub trait WordSymbol {
+    fn is_word(&self) -> bool;
+}
+
+impl WordSymbol for u8 {
+    #[inline]
+    fn is_word(&self) -> bool {
+        self.is_ascii_alphanumeric() || *self == b'_'
+    }
+}
+
+impl WordSymbol for char {
+    #[inline]
+    fn is_word(&self) -> bool {
+        crate::regex::is_word_char(*self)
+    }
+}
+
+impl<V> WordSymbol for Action<V>
+where
+    V: WordSymbol + std::hash::Hash + Eq + Ord + Copy,
+{
+    #[inline]
+    fn is_word(&self) -> bool {
+        match self {
+            Action::Range(v, _) | Action::Single(v) => v.is_word(),
+        }
+    }
+}
+
+impl Context {
+    #[inline]
+    pub fn of<V: WordSymbol>(v: Option<&V>) -> Self {
+        match v {
+            None => Context::Edge,
+            Some(v) if v.is_word() => Context::Word,
+            Some(_) => Context::Other,
+        }
+    }
+}
+
+impl Look {
+    #[inline]
+    pub fn holds(self, before: Context, after: Context) -> bool {
+        match self {
+            Look::Start => before == Context::Edge,
+            Look::End => after == Context::Edge,
+            Look::WordBoundary => (before == Context::Word) != (after == Context::Word),
+            Look::NotWordBoundary => (before == Context::Word) == (after == Context::Word),
+        }
+    }
+    // the same assertion read from right to left, it holds with `before` and `after` swapped
+    #[inline]
+    pub fn reversed(self) -> Self {
+        match self {
+            Look::Start => Look::End,
+            Look::End => Look::Start,
+            look => look,
+        }
+    }
+}
+
+#[cfg(test)]
+mod look_test {
+    use super::{Context, Look};
+
+    #[test]
+    fn holds() {
+        use Context::*;
+        assert!(Look::Start.holds(Edge, Word));
+        assert!(!Look::Start.holds(Other, Edge));
+        assert!(Look::End.holds(Word, Edge));
+        assert!(!Look::End.holds(Edge, Other));
+        assert!(Look::WordBoundary.holds(Edge, Word));
+        assert!(Look::WordBoundary.holds(Word, Other));
+        assert!(!Look::WordBoundary.holds(Other, Edge));
+        assert!(Look::NotWordBoundary.holds(Word, Word));
+        assert!(Look::NotWordBoundary.holds(Edge, Other));
+        assert!(!Look::NotWordBoundary.holds(Word, Edge));
+        for look in [
+            Look::Start,
+            Look::End,
+            Look::WordBoundary,
+            Look::NotWordBoundary,
+        ] {
+            for (before, after) in [(Edge, Word), (Word, Other), (Other, Edge), (Word, Word)] {
+                assert_eq!(
+                    look.holds(before, after),
+                    look.reversed().holds(after, before)
+                );
+            }
+        }
+    }
+    #[test]
+    fn context() {
+        assert_eq!(Context::of::<char>(None), Context::Edge);
+        assert_eq!(Context::of(Some(&'é')), Context::Word);
+        assert_eq!(Context::of(Some(&'-')), Context::Other);
+        assert_eq!(Context::of(Some(&b'_')), Context::Word);
+    }
+}
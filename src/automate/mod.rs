@@ -1,8 +1,14 @@
 mod action;
+mod backtrack;
 mod dfa;
+mod look;
 mod nfa;
+mod pike;
 mod state_machine;
 pub use action::*;
+pub use backtrack::*;
 pub use dfa::*;
+pub use look::*;
 pub use nfa::*;
+pub use pike::*;
 pub use state_machine::*;
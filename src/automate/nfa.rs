@@ -1,16 +1,16 @@
 use std::{
-    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    collections::{BTreeMap, BTreeSet, HashSet},
     hash::Hash,
+    ops::Range,
 };
 
-use super::StateMachine;
-use crate::matches::Matcher;
+use super::{Context, Look, Partition, StateMachine, WordSymbol};
+use crate::matches::{Input, Matcher};
 
 /*
- * the NFA will have the multiply start point
- * and one start point is simple and manage
- * but we still can store the start point visited because
- * the start point is special and it will and always been used.
+ * the NFA will have the multiply start point, every path may begin from any of them, so two
+ * NFA can be merged just by putting their states side by side without a synthetic start.
+ * one start point is still the common case and `new` takes just one.
  */
 type StateSet<T> = BTreeSet<T>;
 
@@ -20,11 +20,15 @@ where
     S: Hash + Eq + Ord + Copy,
     V: Hash + Eq + Ord,
 {
-    pub start_state: S,
+    pub start_states: StateSet<S>,
     end_state: HashSet<S>,
     pub all_state: HashSet<S>,
     pub all_path: BTreeSet<V>,
     maps: BTreeMap<S, BTreeMap<Option<V>, StateSet<S>>>,
+    // the zero width edges, they are kept away from `maps` so the epsilon edges stay `None`
+    looks: BTreeMap<S, BTreeMap<Look, StateSet<S>>>,
+    // the capture slots a state records the position in when entered, only the Pike VM reads them
+    slots: BTreeMap<S, Vec<usize>>,
 }
 
 impl<S, V> Nfa<S, V>
@@ -34,22 +38,30 @@ where
 {
     #[inline]
     pub fn new(start_state: S) -> Self {
+        Self::with_starts([start_state])
+    }
+    #[inline]
+    pub fn with_starts(start_states: impl IntoIterator<Item = S>) -> Self {
         Self {
-            start_state,
+            start_states: start_states.into_iter().collect(),
             end_state: Default::default(),
             all_state: Default::default(),
             maps: Default::default(),
             all_path: Default::default(),
+            looks: Default::default(),
+            slots: Default::default(),
         }
     }
     #[inline]
     pub fn with_capacity(start_state: S, end_state_amount: usize, all_state_amount: usize) -> Self {
         Self {
-            start_state,
+            start_states: [start_state].into(),
             end_state: HashSet::with_capacity(end_state_amount),
             all_state: HashSet::with_capacity(all_state_amount),
             maps: BTreeMap::new(),
             all_path: Default::default(),
+            looks: Default::default(),
+            slots: Default::default(),
         }
     }
     #[inline]
@@ -68,6 +80,36 @@ where
         }
     }
     #[inline]
+    pub fn add_look(&mut self, from: S, look: Look, to: S) {
+        self.looks
+            .entry(from)
+            .or_default()
+            .entry(look)
+            .or_default()
+            .insert(to);
+    }
+    #[inline]
+    pub fn add_slot(&mut self, state: S, slot: usize) {
+        self.slots.entry(state).or_default().push(slot);
+    }
+    #[inline]
+    pub fn slots(&self, state: &S) -> &[usize] {
+        self.slots.get(state).map_or(&[], |slots| slots.as_slice())
+    }
+    // the capture groups, the whole match included as the group 0
+    #[inline]
+    pub fn captures_len(&self) -> usize {
+        self.slots
+            .values()
+            .flatten()
+            .max()
+            .map_or(1, |slot| slot / 2 + 1)
+    }
+    #[inline]
+    pub fn has_looks(&self) -> bool {
+        !self.looks.is_empty()
+    }
+    #[inline]
     pub fn add_states(&mut self, state: S) -> bool {
         self.all_state.insert(state)
     }
@@ -76,36 +118,231 @@ where
         self.all_path.len()
     }
     #[inline]
+    pub fn add_start_state(&mut self, node: S) -> bool {
+        self.start_states.insert(node)
+    }
+    #[inline]
     pub fn add_end_state(&mut self, node: S) -> bool {
         self.end_state.insert(node)
     }
-    fn closure_recursion(&self, state: &S, previous: &mut HashSet<S>) {
-        if let Some(map) = self.maps.get(state) {
-            if let Some(set) = map.get(&None) {
-                for i in set.iter() {
-                    previous.insert(*i);
-                    self.closure_recursion(i, previous);
+    /*
+     * the states reachable from `state` through epsilon edges only, `state` included, an epsilon
+     * cycle is walked once
+     */
+    #[inline]
+    pub fn closure(&self, state: &S) -> HashSet<S> {
+        self.closure_of([*state])
+    }
+    // the same from every start state at once, where any run begins
+    #[inline]
+    pub fn start_closure(&self) -> HashSet<S> {
+        self.closure_of(self.start_states.iter().copied())
+    }
+    fn closure_of(&self, states: impl IntoIterator<Item = S>) -> HashSet<S> {
+        let mut set = HashSet::new();
+        let mut stack: Vec<S> = states.into_iter().collect();
+        while let Some(top) = stack.pop() {
+            if !set.insert(top) {
+                continue;
+            }
+            if let Some(next) = self.maps.get(&top).and_then(|map| map.get(&None)) {
+                stack.extend(next.iter().filter(|s| !set.contains(*s)));
+            }
+        }
+        set
+    }
+    /*
+     * the states reachable from `states` through epsilon edges and the look edges holding
+     * between the symbol `before` the current position and the one `after` it,
+     * `states` themselves are always part of it
+     */
+    pub fn closure_around(
+        &self,
+        states: impl IntoIterator<Item = S>,
+        before: Context,
+        after: Context,
+    ) -> BTreeSet<S> {
+        let mut result = BTreeSet::new();
+        self.closure_into(states, before, after, &mut result);
+        result
+    }
+    // the same walk, skipping the states in `seen`, the new ones are added to it and returned
+    fn closure_into(
+        &self,
+        states: impl IntoIterator<Item = S>,
+        before: Context,
+        after: Context,
+        seen: &mut BTreeSet<S>,
+    ) -> Vec<S> {
+        let mut found = Vec::new();
+        let mut stack: Vec<S> = states.into_iter().collect();
+        while let Some(state) = stack.pop() {
+            if !seen.insert(state) {
+                continue;
+            }
+            found.push(state);
+            if let Some(set) = self.maps.get(&state).and_then(|map| map.get(&None)) {
+                stack.extend(set.iter().filter(|s| !seen.contains(*s)));
+            }
+            if let Some(looks) = self.looks.get(&state) {
+                for (look, set) in looks.iter() {
+                    if look.holds(before, after) {
+                        stack.extend(set.iter().filter(|s| !seen.contains(*s)));
+                    }
                 }
             }
         }
+        found
     }
-    #[inline]
-    pub fn closure(&self, state: &S) -> HashSet<S> {
-        let mut set = HashSet::with_capacity(self.maps.len());
-        let mut queue: VecDeque<&S> = [state].into();
-        queue.reserve(self.all_state.len());
-        while !queue.is_empty() {
-            let top = queue.pop_back().unwrap();
-            if !set.contains(top) {
-                if let Some(map) = self.maps.get(top) {
-                    if let Some(closure) = map.get(&None) {
-                        queue.extend(closure.iter());
-                        set.extend(closure.iter().copied());
+    /*
+     * removes the states no start reaches and the states reaching no end, with their edges,
+     * returns how many there were. A start state reaching no end goes away too.
+     */
+    pub fn trim(&mut self) -> usize {
+        let mut forward: BTreeMap<S, Vec<S>> = BTreeMap::new();
+        let mut backward: BTreeMap<S, Vec<S>> = BTreeMap::new();
+        let targets = self
+            .maps
+            .iter()
+            .flat_map(|(from, map)| map.values().flatten().map(move |to| (*from, *to)));
+        let looks = self
+            .looks
+            .iter()
+            .flat_map(|(from, map)| map.values().flatten().map(move |to| (*from, *to)));
+        for (from, to) in targets.chain(looks) {
+            forward.entry(from).or_default().push(to);
+            backward.entry(to).or_default().push(from);
+        }
+        let walk = |seeds: Vec<S>, edges: &BTreeMap<S, Vec<S>>| {
+            let mut seen = BTreeSet::new();
+            let mut stack = seeds;
+            while let Some(state) = stack.pop() {
+                if seen.insert(state) {
+                    stack.extend(edges.get(&state).into_iter().flatten());
+                }
+            }
+            seen
+        };
+        let reached = walk(self.start_states.iter().copied().collect(), &forward);
+        let reaching = walk(self.end_state.iter().copied().collect(), &backward);
+        let all: BTreeSet<S> = self
+            .all_state
+            .iter()
+            .chain(self.start_states.iter())
+            .chain(self.end_state.iter())
+            .chain(forward.keys())
+            .chain(backward.keys())
+            .copied()
+            .collect();
+        let keep: BTreeSet<S> = reached.intersection(&reaching).copied().collect();
+        let live = |state: &S| keep.contains(state);
+        self.start_states.retain(live);
+        self.end_state.retain(live);
+        self.all_state.retain(live);
+        self.slots.retain(|state, _| live(state));
+        self.maps.retain(|from, map| {
+            map.retain(|_, to| {
+                to.retain(live);
+                !to.is_empty()
+            });
+            live(from) && !map.is_empty()
+        });
+        self.looks.retain(|from, map| {
+            map.retain(|_, to| {
+                to.retain(live);
+                !to.is_empty()
+            });
+            live(from) && !map.is_empty()
+        });
+        self.all_path = self
+            .maps
+            .values()
+            .flat_map(|map| map.keys().flatten())
+            .copied()
+            .collect();
+        all.len() - keep.len()
+    }
+    /*
+     * the same language without any epsilon edge: a state takes the symbol and look edges of
+     * every state in its closure, and accepts when one of them does. The look edges stay, their
+     * targets have taken the edges of their own closure already. The capture slots are dropped.
+     */
+    pub fn remove_epsilons(&self) -> Nfa<S, V> {
+        let mut nfa = Nfa::with_starts(self.start_states.iter().copied());
+        nfa.all_state = self.all_state.clone();
+        nfa.all_path = self.all_path.clone();
+        let states = self
+            .start_states
+            .iter()
+            .chain(self.maps.keys())
+            .chain(self.looks.keys())
+            .chain(self.end_state.iter())
+            .copied()
+            .collect::<BTreeSet<S>>();
+        for state in states {
+            for reached in self.closure(&state) {
+                if self.end_state.contains(&reached) {
+                    nfa.add_end_state(state);
+                }
+                let edges = self.maps.get(&reached).into_iter().flatten();
+                for (v, targets) in edges.filter(|(v, _)| v.is_some()) {
+                    for to in targets {
+                        nfa.add_edges(state, *v, *to);
+                    }
+                }
+                for (look, targets) in self.looks.get(&reached).into_iter().flatten() {
+                    for to in targets {
+                        nfa.add_look(state, *look, *to);
                     }
                 }
             }
         }
-        set
+        nfa
+    }
+    /*
+     * every edge turned around, the end states become the start states and the other way round,
+     * so it accepts the reversed strings. Run from the end of a match it finds where the match
+     * starts. The capture slots are dropped.
+     */
+    pub fn reverse(&self) -> Nfa<S, V> {
+        let mut nfa = Nfa::with_starts(self.end_state.iter().copied());
+        nfa.all_state = self.all_state.clone();
+        nfa.all_path = self.all_path.clone();
+        for state in self.start_states.iter() {
+            nfa.add_end_state(*state);
+        }
+        for (from, map) in self.maps.iter() {
+            for (v, targets) in map.iter() {
+                for to in targets {
+                    nfa.add_edges(*to, *v, *from);
+                }
+            }
+        }
+        for (from, map) in self.looks.iter() {
+            for (look, targets) in map.iter() {
+                for to in targets {
+                    nfa.add_look(*to, look.reversed(), *from);
+                }
+            }
+        }
+        nfa
+    }
+    // the targets of the epsilon edges then of the look edges holding, by priority
+    pub(super) fn zero_width(&self, state: &S, before: Context, after: Context) -> Vec<S> {
+        let mut targets: Vec<S> = self
+            .maps
+            .get(state)
+            .and_then(|map| map.get(&None))
+            .into_iter()
+            .flatten()
+            .copied()
+            .collect();
+        for (look, set) in self.looks.get(state).into_iter().flatten() {
+            if look.holds(before, after) {
+                targets.extend(set.iter());
+            }
+        }
+        targets
     }
     #[inline]
     pub fn move_t(&self, state: &S, path: &V) -> HashSet<S> {
@@ -119,34 +356,218 @@ where
     }
 }
 
-macro_rules! Nfa {
-	() => {
-
-	};
-}
-
 impl<S, V> Nfa<S, V>
 where
     S: Hash + Eq + Ord + Copy,
-    V: Hash + Eq + Ord,
-    Self: StateMachine<State = S, V = Option<V>, NextState = StateSet<S>>,
+    V: Hash + Eq + Ord + Copy + Partition,
 {
-    #[inline]
-    fn next_unvisited_state(
-        &self,
-        state: &S,
-        v: &Option<V>,
-        visited: &mut BTreeMap<S, BTreeSet<S>>,
-    ) -> Option<S> {
-        let get_next = |set: BTreeSet<_>| {
-            visited.get_mut(state).and_then(|visit| {
-                set.iter().fold(None, |s, v| {
-                    s.or_else(|| if visit.contains(v) { s } else { Some(*v) })
+    // the disjoint pieces every label of an edge is made of, see Partition
+    pub fn pieces(&self) -> Vec<V> {
+        let labels: Vec<V> = self
+            .maps
+            .values()
+            .flat_map(|map| map.keys().flatten().copied())
+            .collect();
+        V::partition(&labels)
+    }
+    /*
+     * every edge is cut into the pieces of its label, so the labels of different states no
+     * longer overlap, and `all_path` holds the pieces. The subset construction reads the pieces
+     * on its own, this is for the users of `all_path`.
+     */
+    pub fn partition(&mut self) {
+        let pieces: BTreeSet<V> = self.pieces().into_iter().collect();
+        for map in self.maps.values_mut() {
+            let mut cut: BTreeMap<Option<V>, StateSet<S>> = BTreeMap::new();
+            for (v, targets) in std::mem::take(map) {
+                let Some(v) = v else {
+                    cut.entry(None).or_default().extend(targets);
+                    continue;
+                };
+                let (first, last) = v.bounds();
+                for piece in pieces.range(first..=last) {
+                    cut.entry(Some(*piece))
+                        .or_default()
+                        .extend(targets.iter().copied());
+                }
+            }
+            *map = cut;
+        }
+        self.all_path = pieces;
+    }
+}
+
+/*
+ * combinators over Nfa numbered by usize, the states of the right hand side are moved after the
+ * ones of the left hand side, so both can be numbered from 0, and the pieces are glued by epsilon
+ * edges like the Thompson construction of the regex module does
+ */
+impl<V> Nfa<usize, V>
+where
+    V: Hash + Eq + Ord + Copy,
+{
+    // one more than the highest state found anywhere in the Nfa
+    fn free_state(&self) -> usize {
+        let targets = self.maps.values().flat_map(|map| map.values().flatten());
+        let looks = self.looks.values().flat_map(|map| map.values().flatten());
+        self.start_states
+            .iter()
+            .chain(self.end_state.iter())
+            .chain(self.all_state.iter())
+            .chain(self.maps.keys())
+            .chain(self.looks.keys())
+            .chain(targets)
+            .chain(looks)
+            .max()
+            .map_or(0, |state| state + 1)
+    }
+    fn shift(self, by: usize) -> Self {
+        let set = |set: StateSet<usize>| set.into_iter().map(|state| state + by).collect();
+        Self {
+            start_states: set(self.start_states),
+            end_state: self.end_state.into_iter().map(|state| state + by).collect(),
+            all_state: self.all_state.into_iter().map(|state| state + by).collect(),
+            all_path: self.all_path,
+            maps: self
+                .maps
+                .into_iter()
+                .map(|(from, map)| {
+                    (
+                        from + by,
+                        map.into_iter().map(|(v, to)| (v, set(to))).collect(),
+                    )
                 })
-            })
-        };
-        self.next_state(state, v).and_then(get_next)
+                .collect(),
+            looks: self
+                .looks
+                .into_iter()
+                .map(|(from, map)| {
+                    (
+                        from + by,
+                        map.into_iter().map(|(look, to)| (look, set(to))).collect(),
+                    )
+                })
+                .collect(),
+            slots: self
+                .slots
+                .into_iter()
+                .map(|(state, slots)| (state + by, slots))
+                .collect(),
+        }
+    }
+    // the edges and states of `other`, its start and end states are left to the caller
+    fn absorb(&mut self, other: Self) {
+        self.all_state.extend(other.all_state);
+        self.all_path.extend(other.all_path);
+        self.maps.extend(other.maps);
+        self.looks.extend(other.looks);
+        self.slots.extend(other.slots);
     }
+    #[inline]
+    fn new_state(&mut self) -> usize {
+        let state = self.free_state();
+        self.all_state.insert(state);
+        state
+    }
+    // `self|other`, both side by side, starting from the start states of either
+    pub fn union(mut self, other: Self) -> Self {
+        let other = other.shift(self.free_state());
+        self.all_state.extend(self.start_states.clone());
+        self.all_state.extend(other.start_states.iter());
+        self.start_states.extend(other.start_states.iter());
+        self.end_state.extend(other.end_state.iter());
+        self.absorb(other);
+        self
+    }
+    // `self` then `other`, every end state of `self` goes into the starts of `other`
+    pub fn concat(mut self, other: Self) -> Self {
+        let other = other.shift(self.free_state());
+        self.all_state.extend(other.start_states.iter());
+        for end in std::mem::take(&mut self.end_state) {
+            for &start in other.start_states.iter() {
+                self.add_edges(end, None, start);
+            }
+        }
+        self.end_state = other.end_state.clone();
+        self.absorb(other);
+        self
+    }
+    // `self*`, a new accepting start state, and every end state going back to the old starts
+    pub fn star(mut self) -> Self {
+        self.plus_loop();
+        self.optional()
+    }
+    // `self+`, every end state going back to the start
+    pub fn plus(mut self) -> Self {
+        self.plus_loop();
+        self
+    }
+    // `self?`, a new accepting start state going into the old ones
+    pub fn optional(mut self) -> Self {
+        let start = self.new_state();
+        for old in std::mem::replace(&mut self.start_states, [start].into()) {
+            self.add_edges(start, None, old);
+        }
+        self.add_end_state(start);
+        self
+    }
+    #[inline]
+    fn plus_loop(&mut self) {
+        let ends: Vec<usize> = self.end_state.iter().copied().collect();
+        for end in ends {
+            for start in self.start_states.clone() {
+                if end != start {
+                    self.add_edges(end, None, start);
+                }
+            }
+        }
+    }
+}
+
+/*
+ * the same syntax as Dfa!, plus `from => () => to` for an epsilon edge and `[to, ...]` for
+ * several targets of one edge
+ */
+#[macro_export]
+macro_rules! Nfa {
+    {
+        Start:[$($start:expr),+$(,)*],
+        End:[$($end:expr),+$(,)*],
+        V: {$($edges:tt)+}
+    }=> {
+        {
+            let mut nfa = $crate::automate::Nfa::with_starts([$($start),+]);
+            $(nfa.add_states($start);)+
+            $(
+                nfa.add_states($end);
+                nfa.add_end_state($end);
+            )+
+            $crate::Nfa!(@edges nfa, $($edges)+);
+            nfa
+        }
+    };
+    {Start:$start:expr, $($rest:tt)*} => {
+        $crate::Nfa!{Start:[$start], $($rest)*}
+    };
+    (@edges $nfa:ident,) => {};
+    (@edges $nfa:ident, $from:expr =>($($v:expr)?)=> [$($to:expr),+$(,)*], $($rest:tt)*) => {
+        {
+            let v = $crate::Nfa!(@label $($v)?);
+            $($crate::Nfa!(@edge $nfa, $from, v, $to);)+
+        }
+        $crate::Nfa!(@edges $nfa, $($rest)*);
+    };
+    (@edges $nfa:ident, $from:expr =>($($v:expr)?)=> $to:expr, $($rest:tt)*) => {
+        $crate::Nfa!(@edge $nfa, $from, $crate::Nfa!(@label $($v)?), $to);
+        $crate::Nfa!(@edges $nfa, $($rest)*);
+    };
+    (@label) => { None };
+    (@label $v:expr) => { Some($v) };
+    (@edge $nfa:ident, $from:expr, $v:expr, $to:expr) => {
+        $nfa.add_states($from);
+        $nfa.add_states($to);
+        $nfa.add_edges($from, $v, $to);
+    };
 }
 
 impl<'a, S, V> StateMachine for &'a Nfa<S, V>
@@ -159,7 +580,7 @@ where
     type NextState = &'a StateSet<S>;
     #[inline]
     fn is_end(&self, state: &S) -> bool {
-        self.end_state.get(state).is_some()
+        self.end_state.contains(state)
     }
     #[inline]
     fn next_state(&self, path: &Self::State, v: &Self::V) -> Option<Self::NextState> {
@@ -172,101 +593,442 @@ where
 }
 
 /*
- * if we use a set to store visited start points
- * so we needn't a initial state, we just choose a proper state
- * from NFA all start state, and process the DFS
- * this is kind of interesting and it's good for use, or we can
- * provide another API for a initial state start
+ * a simulation of every path at once: the states reached so far are kept as a set, each symbol
+ * moves the whole set, so the time is O(n·m) for n symbols and m states and nothing is ever
+ * retried. Each state remembers where the earliest path reaching it started, which gives the
+ * leftmost match, and the simulation goes on while a path from that start is alive for the
+ * longest one.
+ *
+ * the input is peeked through an Input, the symbols read past the end of the match to look for
+ * a longer one are left for the next call, and the span is an offset into the whole input. An
+ * empty match takes the symbol after it, so successive calls always go forward.
  */
-impl<S, V, I> Matcher<I> for Nfa<S, V>
+impl<S, V> Nfa<S, V>
 where
-    I: Iterator<Item = V>,
     S: Hash + Eq + Ord + Copy,
-    V: Hash + Eq + Ord + Copy,
-    Self: StateMachine<State = S, V = Option<V>, NextState = StateSet<S>>,
+    V: Hash + Eq + Ord + Copy + WordSymbol,
 {
-    type Matched = Vec<V>;
-    #[inline]
-    /*
-     * Maybe we should use a Matches structure,
-     * because as the iter is not reach its end, and we could find a path to reach the end
-     * state, so we should continue NFA until the iter is end
-     *
-     *  make sure the iter will get correct length,or it will cost a long time on allocate memory
-     *  space for Vec
-     *
-     */
-    fn r#match(&self, iter: &mut I) -> Option<Self::Matched> {
-        let mut stack = Vec::<V>::with_capacity(match iter.size_hint() {
-            (_, Some(x)) => x,
-            (x, _) => x,
-        });
-        /*
-         * what shall we store in the visited?
-         * (state, v) => {state...}
-         * when we back to previous state, we should choose another state to go,
-         * so we should store current state visited state,if from the start state all have been
-         * visited, go back to the previous until back to the start state, to access this control flow
-         * 1. We can add a initial state, ** (initial state, EMPTY) => all start state ** and this
-         *    must be done before the NFA is constructed
-         * 2. For each all state in start state, if we find a road to the end state, the stack will
-         *    contain the result we want, although the value store in will fallback to confirm, but
-         *    all the v have already store in the maps, we could add it to the stack for the final
-         *    result!
-         */
-        let mut visited: BTreeMap<_, _> = Default::default();
-        let mut store: Vec<S> = Vec::with_capacity(self.all_state.len());
-        let mut current_top = 0usize;
-        store.push(self.start_state);
-        /* most of time the S is always normal type like u32 or usize
-         *
-         * if we iterator all the v in the iterator,
-         * and we can't reached the end state,we will get a None,
-         * but if we reached a end state, then we can get the matched value in the iter
-         *
-         * but here comes questions:
-         * 1. If from the current start state we can't reach the end state,shall we just pop
-         *    the stack to a another stack? Or from the first of the stack and start check?
-         *    which way?We already have multiply V store in the stack, and we should use that
-         *    to get the next unique start state next to reach a end,if not,we shall return
-         *    None to tell that current iter can't fit the NFA which means the iter can't match
-         *    the Regular Expression
-         */
-        while !store.is_empty() {
-            for _ in stack[current_top..].iter() {
-                todo!()
+    pub fn find<I>(&self, input: &mut Input<I>) -> Option<Range<usize>>
+    where
+        I: Iterator<Item = V>,
+    {
+        // the states with the start of their path, earliest start first
+        let mut threads: Vec<(S, usize)> = Vec::with_capacity(self.all_state.len());
+        let mut matched: Option<Range<usize>> = None;
+        let mut pos = input.offset();
+        loop {
+            // a match starting later than one already found is never the leftmost
+            if matched.is_none() {
+                threads.extend(self.start_states.iter().map(|state| (*state, pos)));
+                // no thread looks back further than the symbol before, nothing to keep
+                input.advance_to(pos);
+            }
+            let prev = pos.checked_sub(1).and_then(|pos| input.get(pos));
+            let current = input.get(pos);
+            let (before, after) = (Context::of(prev.as_ref()), Context::of(current.as_ref()));
+            let mut seen = BTreeSet::new();
+            let mut closed = Vec::with_capacity(threads.len());
+            for (state, start) in threads.drain(..) {
+                for state in self.closure_into([state], before, after, &mut seen) {
+                    closed.push((state, start));
+                }
+            }
+            if let Some(&(_, start)) = closed.iter().find(|(state, _)| self.is_end(state)) {
+                if matched
+                    .as_ref()
+                    .is_none_or(|matched| start <= matched.start)
+                {
+                    matched = Some(start..pos);
+                }
             }
-            for v in &mut *iter {
-                if let Some(state) = store.last() {
-                    if let Some(next_state) = self
-                        .next_unvisited_state(state, &Some(v), &mut visited)
-                        .or_else(|| self.next_unvisited_state(state, &None, &mut visited))
-                    {
-                        stack.push(v);
-                        if self.is_end(&next_state) {
-                            return Some(stack);
-                            //question: if current is not equal to stack.len(),what shall we do?
-                        } else {
-                            current_top += 1;
-                            visited.get_mut(state).map(|set| set.insert(next_state));
-                            store.push(next_state);
-                        }
-                    } else {
-                        todo!()
+            if let Some(matched) = &matched {
+                closed.retain(|(_, start)| *start <= matched.start);
+            }
+            let Some(v) = current else {
+                break;
+            };
+            let mut moved = BTreeSet::new();
+            for (state, start) in closed {
+                for next in self.move_t(&state, &v) {
+                    if moved.insert(next) {
+                        threads.push((next, start));
                     }
-                } else {
-                    break;
                 }
             }
+            if threads.is_empty() && matched.is_some() {
+                break;
+            }
+            pos += 1;
+        }
+        match &matched {
+            Some(matched) if matched.is_empty() => input.advance_to(matched.end + 1),
+            Some(matched) => input.advance_to(matched.end),
+            None => input.advance_to(pos),
         }
-        None
+        matched
+    }
+}
+
+/*
+ * the symbols of the match Nfa::find gives, the iterator is read through an Input of its own, so
+ * the symbols peeked past the match are taken from it
+ */
+impl<S, V, I> Matcher<I> for Nfa<S, V>
+where
+    I: Iterator<Item = V>,
+    S: Hash + Eq + Ord + Copy,
+    V: Hash + Eq + Ord + Copy + WordSymbol,
+{
+    type Matched = Vec<V>;
+    fn r#match(&self, iter: &mut I) -> Option<Self::Matched> {
+        let mut read = Vec::with_capacity(iter.size_hint().0);
+        let span = self.find(&mut Input::new(iter.inspect(|v| read.push(*v))))?;
+        Some(read[span].to_vec())
     }
 }
 
 #[cfg(test)]
 mod nfa_test {
+    use super::Nfa;
+    use crate::automate::{Action, Context, Look, StateMachine};
+    use crate::matches::{Input, Matcher};
+
+    #[track_caller]
+    fn assert_closure(nfa: &Nfa<usize, u8>, state: usize, expected: &[usize]) {
+        let mut closure: Vec<_> = nfa.closure(&state).into_iter().collect();
+        closure.sort_unstable();
+        assert_eq!(closure, expected);
+    }
+
+    #[test]
+    fn closure_calculate() {
+        let mut nfa = Nfa::new(0);
+        // 0 -> 1 -> 2 -> 0 is an epsilon cycle, 2 -> 3 reads a symbol
+        nfa.add_edges(0, None, 1);
+        nfa.add_edges(1, None, 2);
+        nfa.add_edges(2, None, 0);
+        nfa.add_edges(2, Some(b'a'), 3);
+        nfa.add_edges(3, None, 3);
+        nfa.add_edges(3, None, 4);
+        nfa.add_look(4, Look::End, 5);
+        // reflexive, even for a state without any edge
+        assert_closure(&nfa, 5, &[5]);
+        assert_closure(&nfa, 9, &[9]);
+        // the cycle is walked once from any of its states
+        assert_closure(&nfa, 0, &[0, 1, 2]);
+        assert_closure(&nfa, 1, &[0, 1, 2]);
+        // a self loop, and a look edge is not an epsilon edge
+        assert_closure(&nfa, 3, &[3, 4]);
+        assert_closure(&nfa, 4, &[4]);
+        // the closure the Dfa construction uses agrees when no look is involved
+        for state in 0..5 {
+            let around = nfa.closure_around([state], Context::Word, Context::Word);
+            assert_eq!(nfa.closure(&state), around.into_iter().collect());
+        }
+    }
+    #[test]
+    fn nfa_macro() {
+        let nfa = Nfa! {
+            Start:0,
+            End:[3],
+            V: {
+                0 => (b'a') => [1, 2],
+                1 => () => 3,
+                2 => (b'b') => 3,
+                3 => () => [0, 1 + 1,],
+            }
+        };
+        assert_eq!(nfa.all_state.len(), 4);
+        assert_eq!(nfa.path_len(), 2);
+        let mut closure: Vec<_> = nfa.closure(&3).into_iter().collect();
+        closure.sort_unstable();
+        assert_eq!(closure, [0, 2, 3]);
+        assert_eq!(
+            nfa.find(&mut Input::new(b"xab".iter().copied())),
+            Some(1..3)
+        );
+        assert!((&nfa).is_end(&3) && !(&nfa).is_end(&1));
+        // a target is any expression, like in Dfa!
+        let n = 0;
+        let nfa = Nfa! {
+            Start:n,
+            End:[n + 2],
+            V: {
+                n => (b'a') => n + 1,
+                n + 1 => () => n + 2,
+            }
+        };
+        assert_eq!(nfa.find(&mut Input::new(b"ba".iter().copied())), Some(1..2));
+    }
+    #[test]
+    fn start_states() {
+        let nfa = Nfa! {
+            Start:[0, 3],
+            End:[2, 4],
+            V: {
+                0 => () => 1,
+                1 => (b'a') => 2,
+                3 => (b'b') => 4,
+            }
+        };
+        let mut closure: Vec<_> = nfa.start_closure().into_iter().collect();
+        closure.sort_unstable();
+        assert_eq!(closure, [0, 1, 3]);
+        assert_eq!(nfa.find(&mut Input::new(b"xb".iter().copied())), Some(1..2));
+        assert_eq!(nfa.find(&mut Input::new(b"a".iter().copied())), Some(0..1));
+        let mut nfa = Nfa::new(0);
+        nfa.add_edges(0, Some(b'a'), 1);
+        nfa.add_edges(2, Some(b'b'), 1);
+        nfa.add_end_state(1);
+        assert_eq!(nfa.find(&mut Input::new(b"b".iter().copied())), None);
+        assert!(nfa.add_start_state(2));
+        assert_eq!(nfa.find(&mut Input::new(b"b".iter().copied())), Some(0..1));
+    }
+    #[test]
+    fn trim() {
+        // 2 and 4 reach no end, 3 and 5 are reached from no start
+        let mut nfa = Nfa! {
+            Start:0,
+            End:[1],
+            V: {
+                0 => (b'a') => 1,
+                0 => (b'b') => 2,
+                1 => () => [0, 4],
+                3 => (b'c') => [1, 5],
+            }
+        };
+        nfa.add_look(2, Look::End, 4);
+        assert_eq!(nfa.trim(), 4);
+        assert_eq!(nfa.all_state, [0, 1].into());
+        assert_eq!(nfa.path_len(), 1);
+        assert_eq!((&nfa).next_state(&0, &Some(b'b')), None);
+        assert_eq!(
+            nfa.find(&mut Input::new(b"baa".iter().copied())),
+            Some(1..3)
+        );
+        assert_eq!(nfa.trim(), 0);
+        // nothing reaches the end, the start goes too
+        let mut nfa = Nfa::new(0);
+        nfa.add_edges(0, Some(b'a'), 1);
+        nfa.add_end_state(2);
+        assert_eq!(nfa.trim(), 3);
+        assert!(nfa.start_states.is_empty());
+        assert_eq!(nfa.find(&mut Input::new(b"a".iter().copied())), None);
+    }
     #[test]
-    fn closure_calculate() {}
+    fn remove_epsilons() {
+        // a(b|ε)*c with an epsilon cycle between 1 and 2, and a look edge after the epsilon
+        let mut nfa = Nfa! {
+            Start:0,
+            End:[5],
+            V: {
+                0 => (b'a') => 1,
+                1 => () => 2,
+                2 => () => [1, 3],
+                2 => (b'b') => 1,
+                3 => (b'c') => 4,
+                4 => () => 6,
+            }
+        };
+        nfa.add_look(6, Look::End, 5);
+        let free = nfa.remove_epsilons();
+        for state in 0..7 {
+            assert_eq!((&free).next_state(&state, &None), None);
+        }
+        assert_eq!(free.all_state, nfa.all_state);
+        for s in [&b"abbc"[..], b"ac", b"xac", b"abcx", b"ab", b"c", b""] {
+            assert_eq!(
+                free.find(&mut Input::new(s.iter().copied())),
+                nfa.find(&mut Input::new(s.iter().copied())),
+            );
+        }
+        assert_eq!(
+            free.find(&mut Input::new(b"xabc".iter().copied())),
+            Some(1..4)
+        );
+        // an accepting state reached by epsilon makes the states before it accept
+        let nfa = Nfa! {
+            Start:0,
+            End:[2],
+            V: {
+                0 => () => 1,
+                1 => () => 2,
+                1 => (b'a') => 0,
+            }
+        };
+        let free = nfa.remove_epsilons();
+        assert!((&free).is_end(&0) && (&free).is_end(&1));
+        assert_eq!(
+            free.find(&mut Input::new(b"aa".iter().copied())),
+            Some(0..2)
+        );
+    }
     #[test]
-    fn nfa_match() {}
+    fn reverse() {
+        // a+b$ reads ba+ from the end of the input
+        let mut nfa = Nfa! {
+            Start:0,
+            End:[2],
+            V: {
+                0 => (b'a') => 1,
+                1 => () => 0,
+                1 => (b'b') => 3,
+            }
+        };
+        nfa.add_look(3, Look::End, 2);
+        let reversed = nfa.reverse();
+        assert_eq!(reversed.start_states, [2].into());
+        assert!((&reversed).is_end(&0));
+        assert_eq!(
+            reversed.find(&mut Input::new(b"baax".iter().copied())),
+            Some(0..3)
+        );
+        assert_eq!(
+            reversed.find(&mut Input::new(b"xbaa".iter().copied())),
+            None
+        );
+        // the end of the match of a+b in xaab is 4, reading back from it finds the start
+        let mut back = Input::new(b"xaab".iter().rev().copied());
+        assert_eq!(reversed.find(&mut back), Some(0..3));
+        let twice = reversed.reverse();
+        assert_eq!(
+            twice.find(&mut Input::new(b"xaab".iter().copied())),
+            Some(1..4)
+        );
+    }
+    #[test]
+    fn combinators() {
+        // every piece is numbered from 0
+        let symbol = |v: u8| -> Nfa<usize, u8> {
+            Nfa! {
+                Start:0,
+                End:[1],
+                V: {
+                    0 => (v) => 1,
+                }
+            }
+        };
+        let word = |s: &[u8]| {
+            s.iter()
+                .map(|v| symbol(*v))
+                .reduce(|nfa, next| nfa.concat(next))
+                .unwrap()
+        };
+        // (a|b)*abb
+        let nfa = symbol(b'a').union(symbol(b'b')).star().concat(word(b"abb"));
+        assert_eq!(
+            nfa.find(&mut Input::new(b"xbababbx".iter().copied())),
+            Some(1..7)
+        );
+        assert_eq!(nfa.find(&mut Input::new(b"abab".iter().copied())), None);
+        // a cycle of epsilon edges from a star of an optional must not hang the matcher
+        let nfa = symbol(b'a').optional().star().concat(symbol(b'b'));
+        assert_eq!(
+            nfa.find(&mut Input::new(b"aab".iter().copied())),
+            Some(0..3)
+        );
+        assert_eq!(nfa.find(&mut Input::new(b"b".iter().copied())), Some(0..1));
+        let nfa = word(b"ab").plus();
+        assert_eq!(
+            nfa.find(&mut Input::new(b"xababa".iter().copied())),
+            Some(1..5)
+        );
+        assert_eq!(nfa.find(&mut Input::new(b"".iter().copied())), None);
+        let nfa = word(b"ab").optional();
+        assert_eq!(nfa.find(&mut Input::new(b"ab".iter().copied())), Some(0..2));
+        assert_eq!(nfa.find(&mut Input::new(b"x".iter().copied())), Some(0..0));
+        // the states are renumbered, none is lost or shared
+        let nfa = symbol(b'a').union(symbol(b'b'));
+        assert_eq!(nfa.all_state.len(), 4);
+        let mut ends: Vec<_> = nfa.end_state.iter().copied().collect();
+        ends.sort_unstable();
+        assert_eq!(ends, [1, 3]);
+        assert_eq!(nfa.start_states, [0, 2].into());
+    }
+    #[test]
+    fn overlapping_labels() {
+        // [a-z], e and [c-f] out of three states the start goes into
+        let mut nfa = Nfa::new(0);
+        for (from, v, to) in [
+            (1, Action::Range('a', 'z'), 2),
+            (3, Action::Single('e'), 4),
+            (5, Action::Range('c', 'f'), 6),
+        ] {
+            nfa.add_edges(0, None, from);
+            nfa.add_edges(from, Some(v), to);
+            nfa.add_end_state(to);
+        }
+        let pieces = [
+            Action::Range('a', 'b'),
+            Action::Range('c', 'd'),
+            Action::Single('e'),
+            Action::Single('f'),
+            Action::Range('g', 'z'),
+        ];
+        assert_eq!(nfa.pieces(), pieces);
+        // every label is made of whole pieces, the subset construction reads them one by one
+        let dfa = crate::automate::Dfa::from(&nfa);
+        assert_eq!(dfa.edges().count(), 5);
+        let start = dfa.start_state();
+        let to = |c| dfa.next_state(&start, &Action::Single(c));
+        assert_ne!(to('e'), to('d'));
+        assert_ne!(to('d'), to('a'));
+        assert_eq!(to('d'), to('f'));
+        assert_eq!(to('a'), to('z'));
+        // the edges are rewritten with the pieces, each still reads what its label did
+        nfa.partition();
+        assert_eq!(nfa.all_path.iter().copied().collect::<Vec<_>>(), pieces);
+        let next = |state, c| (&nfa).next_state(&state, &Some(Action::Single(c))).cloned();
+        assert_eq!(next(1, 'a'), Some([2].into()));
+        assert_eq!(next(1, 'e'), Some([2].into()));
+        assert_eq!(next(1, 'z'), Some([2].into()));
+        assert_eq!(next(3, 'e'), Some([4].into()));
+        assert_eq!(next(5, 'c'), Some([6].into()));
+        assert_eq!(next(5, 'b'), None);
+        assert_eq!(
+            (&nfa).next_state(&1, &Some(Action::Range('c', 'd'))),
+            Some(&[2].into())
+        );
+    }
+    #[test]
+    fn nfa_match() {
+        // (a|ab)c with an epsilon loop 4 <-> 5 before the end
+        let mut nfa = Nfa::new(0);
+        nfa.add_edges(0, Some(b'a'), 1);
+        nfa.add_edges(0, Some(b'a'), 2);
+        nfa.add_edges(2, Some(b'b'), 1);
+        nfa.add_edges(1, Some(b'c'), 4);
+        nfa.add_edges(4, None, 5);
+        nfa.add_edges(5, None, 4);
+        nfa.add_end_state(5);
+        let span = |input: &[u8]| nfa.find(&mut Input::new(input.iter().copied()));
+        assert_eq!(span(b"xxabc"), Some(2..5));
+        assert_eq!(span(b"ac"), Some(0..2));
+        assert_eq!(span(b"abxac"), Some(3..5));
+        assert_eq!(span(b"ab"), None);
+        assert_eq!(span(b""), None);
+        // the matched symbols, the symbols peeked past a match are gone with the iterator
+        let mut iter = b"xxabcxacd".iter().copied();
+        assert_eq!(nfa.r#match(&mut iter), Some(b"abc".to_vec()));
+        assert_eq!(nfa.r#match(&mut iter), Some(b"ac".to_vec()));
+        assert_eq!(nfa.r#match(&mut iter), None);
+        // the symbols read past a match are left to the next one, the spans go on counting
+        let mut input = Input::new(b"acxabc".iter().copied());
+        assert_eq!(
+            std::iter::from_fn(|| nfa.find(&mut input)).collect::<Vec<_>>(),
+            vec![0..2, 3..6]
+        );
+        let mut input = Input::new(b"acacabc".iter().copied());
+        assert_eq!(
+            std::iter::from_fn(|| nfa.find(&mut input)).collect::<Vec<_>>(),
+            vec![0..2, 2..4, 4..7]
+        );
+        // a word boundary after a char
+        let mut nfa = Nfa::new(0);
+        nfa.add_edges(0, Some(Action::Single('a')), 1);
+        nfa.add_look(1, Look::WordBoundary, 2);
+        nfa.add_end_state(2);
+        let mut input = Input::new("ab a".chars().map(Action::Single));
+        assert_eq!(nfa.find(&mut input), Some(3..4));
+    }
 }
@@ -0,0 +1,234 @@
+use std::{collections::BTreeSet, hash::Hash, ops::Range};
+
+use super::{Context, Nfa, StateMachine, WordSymbol};
+use crate::matches::{Input, Matcher};
+
+/*
+ * the Pike VM, the same walk over every path at once as the Matcher of Nfa, but each thread
+ * carries the positions its path went through the capture slots of the Nfa. Threads are kept in
+ * the order of priority of their paths, the lower state first among the targets of one state, as
+ * the Thompson construction numbers a greedy loop before its exit and a branch before the next
+ * one, so two threads reaching the same state keep the one a backtracking matcher would try
+ * first.
+ *
+ * so unlike the Matcher of Nfa, which is leftmost longest, this one is leftmost first: a match
+ * stops the threads after it, `a|ab` matches `a` of `ab`.
+ */
+pub struct PikeVm<'a, S, V>
+where
+    S: Hash + Eq + Ord + Copy,
+    V: Hash + Eq + Ord,
+{
+    nfa: &'a Nfa<S, V>,
+    slots: usize,
+}
+
+// the span of every group of a match, counted like the Matcher of Nfa does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures {
+    slots: Vec<Option<usize>>,
+}
+
+impl Captures {
+    #[inline]
+    pub(super) fn new(slots: Vec<Option<usize>>) -> Self {
+        Self { slots }
+    }
+    // None when the group took no part in the match, like `(a)` in `(a)|b` matching `b`
+    #[inline]
+    pub fn get(&self, group: usize) -> Option<Range<usize>> {
+        match self.slots.get(2 * group..2 * group + 2)? {
+            [Some(start), Some(end)] => Some(*start..*end),
+            _ => None,
+        }
+    }
+    // the number of groups, the whole match included
+    #[inline]
+    pub fn groups(&self) -> usize {
+        self.slots.len() / 2
+    }
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = Option<Range<usize>>> + '_ {
+        (0..self.groups()).map(|group| self.get(group))
+    }
+}
+
+type Thread<S> = (S, Vec<Option<usize>>);
+
+impl<'a, S, V> PikeVm<'a, S, V>
+where
+    S: Hash + Eq + Ord + Copy,
+    V: Hash + Eq + Ord + Copy,
+{
+    #[inline]
+    pub fn new(nfa: &'a Nfa<S, V>) -> Self {
+        Self {
+            nfa,
+            slots: 2 * nfa.captures_len(),
+        }
+    }
+    // the closure of one thread in the order of priority, the states already taken are skipped
+    fn follow(
+        &self,
+        thread: Thread<S>,
+        (before, after): (Context, Context),
+        pos: usize,
+        seen: &mut BTreeSet<S>,
+        closed: &mut Vec<Thread<S>>,
+    ) {
+        let mut stack = vec![thread];
+        while let Some((state, mut slots)) = stack.pop() {
+            if !seen.insert(state) {
+                continue;
+            }
+            for slot in self.nfa.slots(&state) {
+                if let Some(slot) = slots.get_mut(*slot) {
+                    *slot = Some(pos);
+                }
+            }
+            let next = self.nfa.zero_width(&state, before, after);
+            for next in next.into_iter().rev() {
+                if !seen.contains(&next) {
+                    stack.push((next, slots.clone()));
+                }
+            }
+            closed.push((state, slots));
+        }
+    }
+}
+
+impl<'a, S, V, I> Matcher<Input<I>> for PikeVm<'a, S, V>
+where
+    I: Iterator<Item = V>,
+    S: Hash + Eq + Ord + Copy,
+    V: Hash + Eq + Ord + Copy + WordSymbol,
+{
+    type Matched = Captures;
+    fn r#match(&self, input: &mut Input<I>) -> Option<Self::Matched> {
+        let mut threads: Vec<Thread<S>> = Vec::with_capacity(self.nfa.all_state.len());
+        let mut matched: Option<Vec<Option<usize>>> = None;
+        let mut pos = input.offset();
+        loop {
+            // the new threads come last, a match starting here is the lowest priority
+            if matched.is_none() {
+                for start in self.nfa.start_states.iter() {
+                    let mut slots = vec![None; self.slots];
+                    slots[0] = Some(pos);
+                    threads.push((*start, slots));
+                }
+                input.advance_to(pos);
+            }
+            let prev = pos.checked_sub(1).and_then(|pos| input.get(pos));
+            let current = input.get(pos);
+            let around = (Context::of(prev.as_ref()), Context::of(current.as_ref()));
+            let mut seen = BTreeSet::new();
+            let mut closed = Vec::with_capacity(threads.len());
+            for thread in threads.drain(..) {
+                self.follow(thread, around, pos, &mut seen, &mut closed);
+            }
+            for (state, mut slots) in closed {
+                if self.nfa.is_end(&state) {
+                    slots[1] = Some(pos);
+                    matched = Some(slots);
+                    break;
+                }
+                let Some(v) = current else {
+                    continue;
+                };
+                for next in self.nfa.next_state(&state, &Some(v)).into_iter().flatten() {
+                    threads.push((*next, slots.clone()));
+                }
+            }
+            if current.is_none() || (threads.is_empty() && matched.is_some()) {
+                break;
+            }
+            pos += 1;
+        }
+        // the symbols read past the match are left to the next one, like the Matcher of Nfa does
+        let captures = matched.map(Captures::new);
+        match captures.as_ref().and_then(|captures| captures.get(0)) {
+            Some(span) if span.is_empty() => input.advance_to(span.end + 1),
+            Some(span) => input.advance_to(span.end),
+            None => input.advance_to(pos),
+        }
+        captures
+    }
+}
+
+#[cfg(test)]
+mod pike_test {
+    use super::{Captures, PikeVm};
+    use crate::automate::{Look, Nfa};
+    use crate::matches::{Input, Matcher};
+
+    #[test]
+    fn captures() {
+        // (a+)(b?), the slots 2 and 3 around the a, 4 and 5 around the b
+        let mut nfa = crate::Nfa! {
+            Start:0,
+            End:[6],
+            V: {
+                0 => () => 1,
+                1 => (b'a') => 2,
+                2 => () => [1, 3],
+                3 => () => 4,
+                4 => (b'b') => 5,
+                4 => () => 5,
+                5 => () => 6,
+            }
+        };
+        for (state, slot) in [(0, 2), (3, 3), (3, 4), (5, 5)] {
+            nfa.add_slot(state, slot);
+        }
+        let vm = PikeVm::new(&nfa);
+        let captures = vm
+            .r#match(&mut Input::new(b"xaab".iter().copied()))
+            .unwrap();
+        assert_eq!(captures.groups(), 3);
+        assert_eq!(captures.get(0), Some(1..4));
+        assert_eq!(captures.get(1), Some(1..3));
+        assert_eq!(captures.get(2), Some(3..4));
+        assert_eq!(captures.get(3), None);
+        let captures = vm.r#match(&mut Input::new(b"a".iter().copied())).unwrap();
+        assert_eq!(
+            captures.iter().collect::<Vec<_>>(),
+            [Some(0..1), Some(0..1), Some(1..1)]
+        );
+        assert_eq!(vm.r#match(&mut Input::new(b"bbb".iter().copied())), None);
+        // successive matches counted from the input start, the symbol a match read to know it
+        // was done is left to the next one
+        for (input, expected) in [(&b"ab aa"[..], [0..2, 3..5]), (b"abab", [0..2, 2..4])] {
+            let mut input = Input::new(input.iter().copied());
+            let spans: Vec<_> = vm.matches(&mut input).filter_map(|c| c.get(0)).collect();
+            assert_eq!(spans, expected);
+        }
+    }
+    #[test]
+    fn priority() {
+        // a|ab, the first branch wins where the Matcher of Nfa takes the longest
+        let nfa = crate::Nfa! {
+            Start:0,
+            End:[3],
+            V: {
+                0 => () => [1, 4],
+                1 => (b'a') => 3,
+                4 => (b'a') => 5,
+                5 => (b'b') => 3,
+            }
+        };
+        let captures: Option<Captures> =
+            PikeVm::new(&nfa).r#match(&mut Input::new(b"ab".iter().copied()));
+        assert_eq!(captures.and_then(|c| c.get(0)), Some(0..1));
+        assert_eq!(nfa.find(&mut Input::new(b"ab".iter().copied())), Some(0..2));
+        // a look edge is only walked where it holds
+        let mut nfa = Nfa::new(0);
+        nfa.add_look(0, Look::WordBoundary, 1);
+        nfa.add_edges(1, Some(b'a'), 2);
+        nfa.add_end_state(2);
+        let vm = PikeVm::new(&nfa);
+        let captures = vm.r#match(&mut Input::new(b"aa a".iter().copied()));
+        assert_eq!(captures.and_then(|c| c.get(0)), Some(0..1));
+        let captures = vm.r#match(&mut Input::new(b"ba a".iter().copied()));
+        assert_eq!(captures.and_then(|c| c.get(0)), Some(3..4));
+    }
+}
@@ -1,2 +1,3 @@
-mod automate;
+pub mod automate;
 pub mod matches;
+pub mod regex;
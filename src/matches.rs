@@ -1,3 +1,6 @@
+use std::collections::VecDeque;
+use std::iter::Fuse;
+
 pub trait Matcher<I>
 where
     I: Iterator,
@@ -50,3 +53,100 @@ where
         (1, self.iter.size_hint().1)
     }
 }
+
+/*
+ * an iterator read through a buffer, a matcher can peek at the symbols after a match without
+ * taking them from the next one. The positions count from the first symbol of the iterator, so
+ * the spans of successive matches are offsets into the whole input, and the symbol right before
+ * the next one is kept for the look behind of `\b`.
+ */
+pub struct Input<I>
+where
+    I: Iterator,
+{
+    iter: Fuse<I>,
+    buffer: VecDeque<I::Item>,
+    // the position of the first symbol of the buffer, the next one to take
+    offset: usize,
+    last: Option<I::Item>,
+}
+
+impl<I> Input<I>
+where
+    I: Iterator,
+    I::Item: Copy,
+{
+    #[inline]
+    pub fn new(iter: I) -> Self {
+        Self {
+            iter: iter.fuse(),
+            buffer: VecDeque::new(),
+            offset: 0,
+            last: None,
+        }
+    }
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    // the symbol at `pos`, reading up to it if needed, a symbol before the last one taken is gone
+    pub fn get(&mut self, pos: usize) -> Option<I::Item> {
+        if pos + 1 == self.offset {
+            return self.last;
+        }
+        assert!(pos >= self.offset, "the symbol at {} is already taken", pos);
+        let index = pos - self.offset;
+        while self.buffer.len() <= index {
+            self.buffer.push_back(self.iter.next()?);
+        }
+        self.buffer.get(index).copied()
+    }
+    // take the symbols before `pos`, or all of them if the input stops first
+    pub fn advance_to(&mut self, pos: usize) {
+        while self.offset < pos {
+            let Some(v) = self.buffer.pop_front().or_else(|| self.iter.next()) else {
+                break;
+            };
+            self.last = Some(v);
+            self.offset += 1;
+        }
+    }
+}
+
+impl<I> Iterator for Input<I>
+where
+    I: Iterator,
+    I::Item: Copy,
+{
+    type Item = I::Item;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let v = self.get(self.offset)?;
+        self.advance_to(self.offset + 1);
+        Some(v)
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        let buffered = self.buffer.len();
+        (lower + buffered, upper.map(|upper| upper + buffered))
+    }
+}
+
+#[cfg(test)]
+mod matches_test {
+    use super::Input;
+
+    #[test]
+    fn input() {
+        let mut input = Input::new("abcd".chars());
+        assert_eq!(input.get(2), Some('c'));
+        assert_eq!(input.offset(), 0);
+        assert_eq!(input.next(), Some('a'));
+        input.advance_to(2);
+        // the symbol right before the offset is still there
+        assert_eq!((input.offset(), input.get(1)), (2, Some('b')));
+        assert_eq!(input.get(4), None);
+        assert_eq!(input.collect::<String>(), "cd");
+    }
+}
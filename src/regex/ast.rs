@@ -0,0 +1,283 @@
+use std::fmt::{self, Display, Write};
+
+use super::{
+    parser::{CLASS_META, META},
+    unicode, CharClass,
+};
+use crate::automate::Look;
+
+/*
+ * The AST is what the parser hands to the automate constructions, every node but Repeat,
+ * Intersection and Complement maps to one Thompson fragment, so keep it as small as the pattern
+ * language allows.
+ *
+ * Display prints it back as the canonical pattern of the tree, parsing that pattern gives the
+ * same tree again: a capture group is printed as it was, any other group as `(?:...)`, flags are gone since `(?i)` is already folded
+ * into the classes, and a class is printed as `.`, a Perl class or a bracket class, negated when
+ * that takes fewer ranges.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ast {
+    Empty,
+    Literal(char),
+    Class(CharClass),
+    Look(Look),
+    Concat(Vec<Ast>),
+    Alternation(Vec<Ast>),
+    // `a&b`, the strings matching every item
+    Intersection(Vec<Ast>),
+    // `~a`, the strings not matching, over every char including the line feed
+    Complement(Box<Ast>),
+    Star(Box<Ast>),
+    Plus(Box<Ast>),
+    Optional(Box<Ast>),
+    // `(a)`, numbered from 1 by the order of the opening parentheses
+    Capture(usize, Box<Ast>),
+    // `{min,max}`, no max for `{min,}`
+    Repeat {
+        ast: Box<Ast>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+// how tightly a node binds, a node printed where a higher one is expected gets a group
+const ALTERNATION: u8 = 0;
+const INTERSECTION: u8 = 1;
+const CONCAT: u8 = 2;
+const REPEAT: u8 = 3;
+const ATOM: u8 = 4;
+
+impl Ast {
+    /*
+     * the number of nodes on the longest path from the root to a leaf, every pass over the tree
+     * recurses that deep, so it is counted with a stack of its own
+     */
+    pub fn depth(&self) -> usize {
+        let mut deepest = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((ast, depth)) = stack.pop() {
+            deepest = deepest.max(depth);
+            match ast {
+                Ast::Concat(items) | Ast::Alternation(items) | Ast::Intersection(items) => {
+                    stack.extend(items.iter().map(|item| (item, depth + 1)))
+                }
+                Ast::Star(ast)
+                | Ast::Plus(ast)
+                | Ast::Optional(ast)
+                | Ast::Repeat { ast, .. }
+                | Ast::Complement(ast)
+                | Ast::Capture(_, ast) => stack.push((ast, depth + 1)),
+                Ast::Empty | Ast::Literal(_) | Ast::Class(_) | Ast::Look(_) => {}
+            }
+        }
+        deepest
+    }
+    pub fn has_word_boundary(&self) -> bool {
+        match self {
+            Ast::Look(look) => matches!(look, Look::WordBoundary | Look::NotWordBoundary),
+            Ast::Concat(items) | Ast::Alternation(items) | Ast::Intersection(items) => {
+                items.iter().any(|ast| ast.has_word_boundary())
+            }
+            Ast::Star(ast)
+            | Ast::Plus(ast)
+            | Ast::Optional(ast)
+            | Ast::Repeat { ast, .. }
+            | Ast::Complement(ast)
+            | Ast::Capture(_, ast) => ast.has_word_boundary(),
+            Ast::Empty | Ast::Literal(_) | Ast::Class(_) => false,
+        }
+    }
+}
+
+impl Ast {
+    #[inline]
+    fn precedence(&self) -> u8 {
+        match self {
+            Ast::Empty | Ast::Alternation(_) => ALTERNATION,
+            Ast::Intersection(_) => INTERSECTION,
+            Ast::Concat(_) => CONCAT,
+            Ast::Star(_)
+            | Ast::Plus(_)
+            | Ast::Optional(_)
+            | Ast::Repeat { .. }
+            | Ast::Complement(_) => REPEAT,
+            Ast::Literal(_) | Ast::Class(_) | Ast::Look(_) | Ast::Capture(..) => ATOM,
+        }
+    }
+    fn write(&self, f: &mut fmt::Formatter<'_>, precedence: u8) -> fmt::Result {
+        if self.precedence() < precedence {
+            f.write_str("(?:")?;
+            self.write(f, ALTERNATION)?;
+            return f.write_char(')');
+        }
+        match self {
+            Ast::Empty => Ok(()),
+            Ast::Literal(c) => write_char(f, *c, META),
+            Ast::Class(class) => write_class(f, class),
+            Ast::Look(look) => f.write_str(match look {
+                Look::Start => "^",
+                Look::End => "$",
+                Look::WordBoundary => "\\b",
+                Look::NotWordBoundary => "\\B",
+            }),
+            Ast::Concat(items) => items.iter().try_for_each(|item| item.write(f, REPEAT)),
+            Ast::Alternation(branches) => {
+                for (i, branch) in branches.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char('|')?;
+                    }
+                    branch.write(f, INTERSECTION)?;
+                }
+                Ok(())
+            }
+            Ast::Intersection(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char('&')?;
+                    }
+                    item.write(f, CONCAT)?;
+                }
+                Ok(())
+            }
+            Ast::Capture(_, ast) => {
+                f.write_char('(')?;
+                ast.write(f, ALTERNATION)?;
+                f.write_char(')')
+            }
+            Ast::Complement(ast) => {
+                f.write_char('~')?;
+                ast.write(f, REPEAT)
+            }
+            Ast::Star(ast) => {
+                ast.write(f, ATOM)?;
+                f.write_char('*')
+            }
+            Ast::Plus(ast) => {
+                ast.write(f, ATOM)?;
+                f.write_char('+')
+            }
+            Ast::Optional(ast) => {
+                ast.write(f, ATOM)?;
+                f.write_char('?')
+            }
+            Ast::Repeat { ast, min, max } => {
+                ast.write(f, ATOM)?;
+                match max {
+                    Some(max) if max == min => write!(f, "{{{}}}", min),
+                    Some(max) => write!(f, "{{{},{}}}", min, max),
+                    None => write!(f, "{{{},}}", min),
+                }
+            }
+        }
+    }
+}
+
+fn write_char(f: &mut fmt::Formatter<'_>, c: char, meta: &str) -> fmt::Result {
+    match c {
+        '\n' => f.write_str("\\n"),
+        '\t' => f.write_str("\\t"),
+        '\r' => f.write_str("\\r"),
+        c if !unicode::is_printable(c) => write!(f, "\\u{{{:X}}}", c as u32),
+        c if meta.contains(c) => write!(f, "\\{}", c),
+        c => f.write_char(c),
+    }
+}
+
+fn write_class(f: &mut fmt::Formatter<'_>, class: &CharClass) -> fmt::Result {
+    if *class == CharClass::dot() {
+        return f.write_char('.');
+    }
+    for perl in ['d', 'D', 'w', 'W', 's', 'S'] {
+        if unicode::perl(perl).as_ref() == Some(class) {
+            return write!(f, "\\{}", perl);
+        }
+    }
+    let negated = class.negate();
+    // an empty class can only be written negated, and a full one never is: the `]` of `[^]` would
+    // be read as a char
+    let (prefix, ranges) = if class.ranges().is_empty()
+        || (!negated.ranges().is_empty() && negated.ranges().len() < class.ranges().len())
+    {
+        ("[^", negated.ranges().to_vec())
+    } else {
+        ("[", class.ranges().to_vec())
+    };
+    f.write_str(prefix)?;
+    for (start, end) in ranges {
+        write_char(f, start, CLASS_META)?;
+        if start != end {
+            f.write_char('-')?;
+            write_char(f, end, CLASS_META)?;
+        }
+    }
+    f.write_char(']')
+}
+
+impl Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, ALTERNATION)
+    }
+}
+
+#[cfg(test)]
+mod ast_test {
+    use crate::regex::{parse_ast, RegexBuilder};
+
+    #[track_caller]
+    fn canonical(pattern: &str) -> String {
+        let ast = parse_ast(pattern).unwrap();
+        let printed = ast.to_string();
+        assert_eq!(parse_ast(&printed).unwrap(), ast, "{:?}", printed);
+        printed
+    }
+
+    #[test]
+    fn canonical_pattern() {
+        assert_eq!(canonical(""), "");
+        assert_eq!(canonical("ab|c"), "ab|c");
+        assert_eq!(canonical("a|(?:b|c)"), "a|(?:b|c)");
+        assert_eq!(canonical("a|(b|c)"), "a|(b|c)");
+        assert_eq!(canonical("(?:ab)*c+"), "(?:ab)*c+");
+        assert_eq!(canonical("(ab)*c+"), "(ab)*c+");
+        assert_eq!(canonical("a(?:bc)"), "a(?:bc)");
+        assert_eq!(canonical("(?:a*)?"), "(?:a*)?");
+        assert_eq!(canonical("((a)(?:b)(c))"), "((a)b(c))");
+        assert_eq!(canonical("a{2}b{2,}c{2,3}"), "a{2}b{2,}c{2,3}");
+        assert_eq!(canonical("(?:)*|"), "(?:)*|(?:)");
+        assert_eq!(canonical("()*|"), "()*|(?:)");
+        assert_eq!(canonical("[a-cb-f_]"), "[_a-f]");
+        assert_eq!(canonical("[^a]"), "[^a]");
+        assert_eq!(canonical("[-\\]^]"), "[\\-\\]-\\^]");
+        assert_eq!(canonical(".\\d\\W\\s"), ".\\d\\W\\s");
+        assert_eq!(canonical("[\\d]"), "\\d");
+        assert_eq!(canonical("\\*\\{\\n\\x01"), "\\*\\{\\n\\u{1}");
+        assert_eq!(canonical("^\\bé\\B$"), "^\\bé\\B$");
+        assert_eq!(canonical("[^\\x00-\\u{10FFFF}]"), "[^\\u{0}-\\u{10FFFF}]");
+        assert_eq!(canonical("[\\s\\S]"), "[\\u{0}-\\u{10FFFF}]");
+        assert_eq!(canonical("(?i)a1"), "[Aa]1");
+        assert_eq!(canonical("~(?:.*a.*)&b+|c"), "~(?:.*a.*)&b+|c");
+        assert_eq!(canonical("(?:a|b)&c"), "(?:a|b)&c");
+        assert_eq!(canonical("~a*b&(?:c&d)"), "~a*b&(?:c&d)");
+        assert_eq!(canonical("(?:~a)*~~b\\&\\~[&~]"), "(?:~a)*~~b\\&\\~[&~]");
+        assert_eq!(canonical("~(.*a.*)&(b)"), "~(.*a.*)&(b)");
+        assert_eq!(canonical("a\\u{A0}\\u{200B} "), "a\\u{A0}\\u{200B} ");
+        for pattern in [
+            r"\p{Lu}+",
+            r"(?i)σ|k",
+            r"[\w-]{1,3}",
+            r"(a|b)*abb|^$",
+            r"a?+*",
+            r"[\s\S]",
+            r"[^\s\S]",
+        ] {
+            let printed = canonical(pattern);
+            // printing is stable once canonical
+            assert_eq!(canonical(&printed), printed);
+        }
+        // the flags are printed folded, the pattern does not need the builder option any more
+        let builder = RegexBuilder::new().case_insensitive(true);
+        let printed = builder.parse_ast("ab").unwrap().to_string();
+        assert_eq!(printed, "[Aa][Bb]");
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+
+use super::{derivative, range, runs, Ast, CharClass, ParseErrorKind};
+use crate::automate::{Action, Dfa, DfaBuilder, DfaError};
+
+/*
+ * `&` and `~` have no Nfa fragment, their operands are determinized and combined as Dfa, by the
+ * product for `&` and by completing then swapping the accepting states for `~`. The Nfa
+ * constructions embed the result like any other sub automaton.
+ *
+ * the alphabet cuts the chars wherever a label of an operand starts or stops, so every label is
+ * made of whole symbols of it, and where the word class does when the pattern around needs its
+ * classes split for `\b`. An operand can not hold an assertion, it is determinized by
+ * derivatives.
+ */
+pub(crate) fn compile(
+    ast: &Ast,
+    max_states: usize,
+    words: Option<&CharClass>,
+) -> Result<Dfa<usize, Action<char>>, ParseErrorKind> {
+    // the product can grow to the sizes of its operands multiplied, it stops at the same limit
+    let builder = DfaBuilder::new().max_states(max_states);
+    let dfa = match ast {
+        Ast::Intersection(items) => {
+            let dfas = items
+                .iter()
+                .map(|item| operand(item, max_states, words))
+                .collect::<Result<Vec<_>, _>>()?;
+            let alphabet = alphabet(&dfas, words);
+            let mut iter = dfas.into_iter();
+            let mut dfa = iter.next().unwrap();
+            for other in iter {
+                dfa = builder
+                    .intersection(&dfa, &other, &alphabet)
+                    .map_err(|error| kind(error, max_states))?;
+            }
+            dfa
+        }
+        Ast::Complement(ast) => {
+            let dfa = operand(ast, max_states, words)?;
+            let alphabet = alphabet(std::slice::from_ref(&dfa), words);
+            builder
+                .complement(&dfa, &alphabet)
+                .map_err(|error| kind(error, max_states))?
+        }
+        _ => unreachable!("only `&` and `~` are combined as Dfa"),
+    };
+    Ok(dfa)
+}
+
+#[inline]
+fn kind(error: DfaError, max_states: usize) -> ParseErrorKind {
+    match error {
+        DfaError::TooLarge => ParseErrorKind::TooManyStates(max_states),
+        DfaError::HasLooks => ParseErrorKind::UnsupportedLook,
+        // no cancel flag, and the pieces of the alphabet never give a state two edges
+        DfaError::Cancelled | DfaError::InvalidRelationship => {
+            unreachable!("{:?} from the product or the complement", error)
+        }
+    }
+}
+
+fn operand(
+    ast: &Ast,
+    max_states: usize,
+    words: Option<&CharClass>,
+) -> Result<Dfa<usize, Action<char>>, ParseErrorKind> {
+    match ast {
+        Ast::Intersection(_) | Ast::Complement(_) => compile(ast, max_states, words),
+        // derivatives stop at the state limit, the subset construction has none
+        ast => derivative::build(ast, max_states),
+    }
+}
+
+fn alphabet(dfas: &[Dfa<usize, Action<char>>], words: Option<&CharClass>) -> Vec<Action<char>> {
+    let labels = dfas
+        .iter()
+        .flat_map(|dfa| dfa.edges())
+        .map(|(_, label, _)| range(label));
+    runs(
+        labels.chain(
+            words
+                .iter()
+                .flat_map(|words| words.ranges().iter().copied()),
+        ),
+    )
+}
+
+// the labels of the edges between each pair of states, as one class per pair
+pub(crate) fn labels(dfa: &Dfa<usize, Action<char>>) -> BTreeMap<(usize, usize), CharClass> {
+    let mut labels: BTreeMap<(usize, usize), CharClass> = BTreeMap::new();
+    for (from, label, to) in dfa.edges() {
+        let (start, end) = range(label);
+        labels.entry((from, to)).or_default().push(start, end);
+    }
+    labels
+}
@@ -0,0 +1,214 @@
+use crate::automate::{Action, Partition};
+
+/*
+ * a set of chars stored as sorted, disjoint and non adjacent ranges, so one class becomes as few
+ * Action as possible and the labels of one class never overlap each other
+ */
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct CharClass {
+    ranges: Vec<(char, char)>,
+}
+
+#[inline]
+pub(crate) fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        char::MAX => None,
+        c => char::from_u32(c as u32 + 1),
+    }
+}
+
+#[inline]
+pub(crate) fn prev_char(c: char) -> Option<char> {
+    match c {
+        '\u{E000}' => Some('\u{D7FF}'),
+        '\0' => None,
+        c => char::from_u32(c as u32 - 1),
+    }
+}
+
+// one char ranges must be a Single, `Action::Range(c, c)` is not a valid label
+#[inline]
+pub(crate) fn action(start: char, end: char) -> Action<char> {
+    if start == end {
+        Action::Single(start)
+    } else {
+        Action::Range(start, end)
+    }
+}
+
+// the chars of a label, the other way round from `action`
+#[inline]
+pub(crate) fn range(label: Action<char>) -> (char, char) {
+    match label {
+        Action::Range(start, end) => (start, end),
+        Action::Single(c) => (c, c),
+    }
+}
+
+/*
+ * all the chars cut wherever one of the ranges starts or stops, so every range is a union of
+ * runs and the chars of one run are in the same ranges, the runs outside of them included
+ */
+pub(crate) fn runs(ranges: impl IntoIterator<Item = (char, char)>) -> Vec<Action<char>> {
+    let mut labels: Vec<Action<char>> = ranges
+        .into_iter()
+        .map(|(start, end)| action(start, end))
+        .collect();
+    labels.push(Action::Range('\0', char::MAX));
+    Action::partition(&labels)
+}
+
+impl CharClass {
+    pub fn new(ranges: impl IntoIterator<Item = (char, char)>) -> Self {
+        let mut class = Self {
+            ranges: ranges.into_iter().collect(),
+        };
+        class.canonicalize();
+        class
+    }
+    // `.` matches everything but the line feed
+    #[inline]
+    pub fn dot() -> Self {
+        Self::new([('\0', '\t'), ('\u{B}', char::MAX)])
+    }
+    #[inline]
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+    #[inline]
+    pub fn contains(&self, c: char) -> bool {
+        self.ranges
+            .binary_search_by(|&(start, end)| {
+                if end < c {
+                    std::cmp::Ordering::Less
+                } else if start > c {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+    #[inline]
+    pub fn push(&mut self, start: char, end: char) {
+        self.ranges.push((start, end));
+        self.canonicalize();
+    }
+    #[inline]
+    pub fn union(&mut self, other: &CharClass) {
+        self.ranges.extend_from_slice(&other.ranges);
+        self.canonicalize();
+    }
+    fn canonicalize(&mut self) {
+        self.ranges.sort_unstable();
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(self.ranges.len());
+        for &(start, end) in self.ranges.iter() {
+            match merged.last_mut() {
+                Some((_, last)) if next_char(*last).is_none_or(|c| c >= start) => {
+                    *last = (*last).max(end);
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+        self.ranges = merged;
+    }
+    pub fn intersect(&self, other: &CharClass) -> Self {
+        let mut ranges = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let ((s1, e1), (s2, e2)) = (self.ranges[i], other.ranges[j]);
+            if s1.max(s2) <= e1.min(e2) {
+                ranges.push((s1.max(s2), e1.min(e2)));
+            }
+            if e1 < e2 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        Self { ranges }
+    }
+    // complement against the whole `char` range, the surrogate gap is skipped by next_char
+    pub fn negate(&self) -> Self {
+        let mut ranges = Vec::with_capacity(self.ranges.len() + 1);
+        let mut start = Some('\0');
+        for &(s, e) in self.ranges.iter() {
+            if let Some(c) = start {
+                if c < s {
+                    ranges.push((c, prev_char(s).unwrap()));
+                }
+            }
+            start = next_char(e);
+        }
+        if let Some(c) = start {
+            ranges.push((c, char::MAX));
+        }
+        Self { ranges }
+    }
+    pub fn actions(&self) -> impl Iterator<Item = Action<char>> + '_ {
+        self.ranges.iter().map(|&(start, end)| action(start, end))
+    }
+}
+
+#[cfg(test)]
+mod class_test {
+    use super::{runs, CharClass};
+    use crate::automate::Action;
+
+    #[test]
+    fn runs_cover_every_char() {
+        assert_eq!(
+            runs([('b', 'd'), ('c', 'c')]),
+            [
+                Action::Range('\0', 'a'),
+                Action::Single('b'),
+                Action::Single('c'),
+                Action::Single('d'),
+                Action::Range('e', char::MAX),
+            ]
+        );
+        assert_eq!(runs([]), [Action::Range('\0', char::MAX)]);
+    }
+    #[test]
+    fn canonical_ranges() {
+        let class = CharClass::new([('x', 'z'), ('a', 'c'), ('b', 'f'), ('g', 'g')]);
+        assert_eq!(class.ranges, vec![('a', 'g'), ('x', 'z')]);
+    }
+    #[test]
+    fn negate_full_range() {
+        let class = CharClass::new([('\0', 'a'), ('c', '\u{D7FF}')]);
+        assert_eq!(
+            class.negate().ranges,
+            vec![('b', 'b'), ('\u{E000}', char::MAX)]
+        );
+        assert_eq!(class.negate().negate(), class);
+        assert_eq!(CharClass::default().negate().negate(), CharClass::default());
+    }
+    #[test]
+    fn intersect() {
+        let class = CharClass::new([('a', 'f'), ('x', 'z')]);
+        let other = CharClass::new([('0', 'b'), ('d', 'y')]);
+        assert_eq!(
+            class.intersect(&other),
+            CharClass::new([('a', 'b'), ('d', 'f'), ('x', 'y')])
+        );
+        assert_eq!(class.intersect(&class.negate()), CharClass::default());
+    }
+    #[test]
+    fn contains() {
+        let class = CharClass::new([('a', 'f'), ('x', 'z'), ('_', '_')]);
+        assert!(class.contains('a') && class.contains('f') && class.contains('_'));
+        assert!(class.contains('y'));
+        assert!(!class.contains('g') && !class.contains('`') && !class.contains('{'));
+        assert!(!CharClass::default().contains('a'));
+    }
+    #[test]
+    fn actions() {
+        let class = CharClass::new([('0', '9'), ('_', '_')]);
+        assert_eq!(
+            class.actions().collect::<Vec<_>>(),
+            vec![Action::Range('0', '9'), Action::Single('_')]
+        );
+    }
+}
@@ -0,0 +1,478 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{action, next_char, range, Ast, CharClass, ParseErrorKind};
+use crate::automate::{Action, Dfa};
+
+/*
+ * Brzozowski derivatives, the derivative of a regex by a char is the regex of what may follow
+ * that char, so a regex is a state of the Dfa and its derivatives are where the edges go. The
+ * smart constructors keep the regexes in a normal form, alternations and intersections sorted,
+ * flattened and deduplicated, so the derivatives of a regex end up being finitely many.
+ *
+ * intersection and complement only need their derivative and nullability, which is why they are
+ * here and not in the Nfa constructions. Assertions have neither, a pattern with one is refused.
+ *
+ * the regexes live in one arena and are hash-consed, equal regexes get the same id, so a state is
+ * compared and hashed by its id whatever its size. A bounded repeat is kept as a counter that the
+ * derivative decrements, `a{5000}` is one term and not 5000 copies of `a`.
+ */
+type Id = usize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Re {
+    // matches nothing, not even the empty string
+    Nothing,
+    Epsilon,
+    Class(CharClass),
+    // the left item is never a Concat
+    Concat(Id, Id),
+    Or(Vec<Id>),
+    And(Vec<Id>),
+    Star(Id),
+    Not(Id),
+    // `{min,max}`, no max for `{min,}`
+    Repeat(Id, u32, Option<u32>),
+}
+
+const NOTHING: Id = 0;
+const EPSILON: Id = 1;
+// `~(?:)`, every string
+const ANYTHING: Id = 2;
+
+struct Terms {
+    terms: Vec<Re>,
+    nullable: Vec<bool>,
+    ids: HashMap<Re, Id>,
+    // the derivatives already taken, by regex and char
+    derived: HashMap<(Id, char), Id>,
+}
+
+impl Terms {
+    fn new() -> Self {
+        let mut terms = Terms {
+            terms: Vec::new(),
+            nullable: Vec::new(),
+            ids: HashMap::new(),
+            derived: HashMap::new(),
+        };
+        terms.intern(Re::Nothing);
+        terms.intern(Re::Epsilon);
+        terms.intern(Re::Not(NOTHING));
+        terms
+    }
+    fn intern(&mut self, re: Re) -> Id {
+        if let Some(&id) = self.ids.get(&re) {
+            return id;
+        }
+        let nullable = match &re {
+            Re::Nothing | Re::Class(_) => false,
+            Re::Epsilon | Re::Star(_) => true,
+            Re::Concat(r, s) => self.nullable[*r] && self.nullable[*s],
+            Re::Or(items) => items.iter().any(|&r| self.nullable[r]),
+            Re::And(items) => items.iter().all(|&r| self.nullable[r]),
+            Re::Not(r) => !self.nullable[*r],
+            Re::Repeat(r, min, _) => *min == 0 || self.nullable[*r],
+        };
+        let id = self.terms.len();
+        self.terms.push(re.clone());
+        self.nullable.push(nullable);
+        self.ids.insert(re, id);
+        id
+    }
+    #[inline]
+    fn nullable(&self, r: Id) -> bool {
+        self.nullable[r]
+    }
+    fn class(&mut self, class: CharClass) -> Id {
+        if class.ranges().is_empty() {
+            NOTHING
+        } else {
+            self.intern(Re::Class(class))
+        }
+    }
+    fn concat(&mut self, r: Id, s: Id) -> Id {
+        if r == NOTHING || s == NOTHING {
+            return NOTHING;
+        }
+        // keep it leaning to the right, so `(ab)c` and `a(bc)` are the same state
+        let mut items = Vec::new();
+        let mut rest = r;
+        while let Re::Concat(item, tail) = self.terms[rest] {
+            items.push(item);
+            rest = tail;
+        }
+        items.push(rest);
+        items.into_iter().rev().fold(s, |s, item| match (item, s) {
+            (EPSILON, s) => s,
+            (r, EPSILON) => r,
+            (r, s) => self.intern(Re::Concat(r, s)),
+        })
+    }
+    fn or(&mut self, items: impl IntoIterator<Item = Id>) -> Id {
+        let mut stack: Vec<Id> = items.into_iter().collect();
+        let mut class = CharClass::default();
+        let mut items = Vec::with_capacity(stack.len());
+        while let Some(item) = stack.pop() {
+            match &self.terms[item] {
+                Re::Nothing => {}
+                Re::Or(inner) => stack.extend(inner),
+                // all the classes of an alternation make one class
+                Re::Class(other) => class.union(other),
+                _ => items.push(item),
+            }
+        }
+        if !class.ranges().is_empty() {
+            items.push(self.intern(Re::Class(class)));
+        }
+        if items.contains(&ANYTHING) {
+            return ANYTHING;
+        }
+        items.sort_unstable();
+        items.dedup();
+        match items.len() {
+            0 => NOTHING,
+            1 => items[0],
+            _ => self.intern(Re::Or(items)),
+        }
+    }
+    fn and(&mut self, items: impl IntoIterator<Item = Id>) -> Id {
+        let mut stack: Vec<Id> = items.into_iter().collect();
+        let mut class: Option<CharClass> = None;
+        let mut items = Vec::with_capacity(stack.len());
+        while let Some(item) = stack.pop() {
+            match &self.terms[item] {
+                Re::Nothing => return NOTHING,
+                Re::And(inner) => stack.extend(inner),
+                Re::Class(other) => {
+                    class = Some(match class {
+                        Some(class) => class.intersect(other),
+                        None => other.clone(),
+                    })
+                }
+                _ if item == ANYTHING => {}
+                _ => items.push(item),
+            }
+        }
+        match class.map(|class| self.class(class)) {
+            Some(NOTHING) => return NOTHING,
+            Some(class) => items.push(class),
+            None => {}
+        }
+        items.sort_unstable();
+        items.dedup();
+        match items.len() {
+            0 => ANYTHING,
+            1 => items[0],
+            _ => self.intern(Re::And(items)),
+        }
+    }
+    fn star(&mut self, r: Id) -> Id {
+        match self.terms[r] {
+            Re::Nothing | Re::Epsilon => EPSILON,
+            Re::Star(_) => r,
+            _ => self.intern(Re::Star(r)),
+        }
+    }
+    fn not(&mut self, r: Id) -> Id {
+        match self.terms[r] {
+            Re::Not(r) => r,
+            _ => self.intern(Re::Not(r)),
+        }
+    }
+    fn repeat(&mut self, r: Id, min: u32, max: Option<u32>) -> Id {
+        // empty iterations of a nullable regex make up for any min
+        let min = if self.nullable(r) { 0 } else { min };
+        match (min, max) {
+            (_, Some(0)) => EPSILON,
+            _ if r == EPSILON => EPSILON,
+            (0, _) if r == NOTHING => EPSILON,
+            _ if r == NOTHING => NOTHING,
+            (0, None) => self.star(r),
+            (1, Some(1)) => r,
+            _ => self.intern(Re::Repeat(r, min, max)),
+        }
+    }
+    fn derive(&mut self, r: Id, c: char) -> Id {
+        if let Some(&derived) = self.derived.get(&(r, c)) {
+            return derived;
+        }
+        let derived = match self.terms[r].clone() {
+            Re::Nothing | Re::Epsilon => NOTHING,
+            Re::Class(class) if class.contains(c) => EPSILON,
+            Re::Class(_) => NOTHING,
+            Re::Concat(..) => {
+                // down the items, each one after a nullable one may read c as well
+                let mut items = Vec::new();
+                let mut rest = r;
+                loop {
+                    match self.terms[rest] {
+                        Re::Concat(item, tail) => {
+                            let derived = self.derive(item, c);
+                            items.push(self.concat(derived, tail));
+                            if !self.nullable(item) {
+                                break;
+                            }
+                            rest = tail;
+                        }
+                        _ => {
+                            items.push(self.derive(rest, c));
+                            break;
+                        }
+                    }
+                }
+                self.or(items)
+            }
+            Re::Or(items) => {
+                let items: Vec<Id> = items.into_iter().map(|r| self.derive(r, c)).collect();
+                self.or(items)
+            }
+            Re::And(items) => {
+                let items: Vec<Id> = items.into_iter().map(|r| self.derive(r, c)).collect();
+                self.and(items)
+            }
+            Re::Star(s) => {
+                let derived = self.derive(s, c);
+                self.concat(derived, r)
+            }
+            Re::Not(s) => {
+                let derived = self.derive(s, c);
+                self.not(derived)
+            }
+            // one iteration started, the rest counts one less
+            Re::Repeat(s, min, max) => {
+                let derived = self.derive(s, c);
+                let rest = self.repeat(s, min.saturating_sub(1), max.map(|max| max - 1));
+                self.concat(derived, rest)
+            }
+        };
+        self.derived.insert((r, c), derived);
+        derived
+    }
+    // the ranges of every class in the regex, the derivative is the same all along a run of them
+    fn ranges(&self, r: Id) -> Vec<(char, char)> {
+        let mut ranges = Vec::new();
+        let mut seen = HashSet::from([r]);
+        let mut stack = vec![r];
+        while let Some(r) = stack.pop() {
+            let mut visit = |s: Id| seen.insert(s).then_some(s);
+            match &self.terms[r] {
+                Re::Nothing | Re::Epsilon => {}
+                Re::Class(class) => ranges.extend_from_slice(class.ranges()),
+                Re::Concat(r, s) => stack.extend([*r, *s].into_iter().filter_map(visit)),
+                Re::Or(items) | Re::And(items) => {
+                    stack.extend(items.iter().filter_map(|&r| visit(r)))
+                }
+                Re::Star(r) | Re::Not(r) | Re::Repeat(r, ..) => stack.extend(visit(*r)),
+            }
+        }
+        ranges
+    }
+    fn term(&mut self, ast: &Ast) -> Result<Id, ParseErrorKind> {
+        Ok(match ast {
+            Ast::Empty => EPSILON,
+            Ast::Literal(c) => self.class(CharClass::new([(*c, *c)])),
+            Ast::Class(class) => self.class(class.clone()),
+            Ast::Look(_) => return Err(ParseErrorKind::UnsupportedLook),
+            Ast::Concat(items) => {
+                let mut re = EPSILON;
+                for item in items.iter().rev() {
+                    let item = self.term(item)?;
+                    re = self.concat(item, re);
+                }
+                re
+            }
+            Ast::Alternation(branches) => {
+                let branches = branches
+                    .iter()
+                    .map(|branch| self.term(branch))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.or(branches)
+            }
+            Ast::Capture(_, ast) => self.term(ast)?,
+            Ast::Star(ast) => {
+                let re = self.term(ast)?;
+                self.star(re)
+            }
+            Ast::Plus(ast) => {
+                let re = self.term(ast)?;
+                let star = self.star(re);
+                self.concat(re, star)
+            }
+            Ast::Optional(ast) => {
+                let re = self.term(ast)?;
+                self.or([EPSILON, re])
+            }
+            Ast::Intersection(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| self.term(item))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.and(items)
+            }
+            Ast::Complement(ast) => {
+                let re = self.term(ast)?;
+                self.not(re)
+            }
+            Ast::Repeat { ast, min, max } => {
+                let re = self.term(ast)?;
+                self.repeat(re, *min, *max)
+            }
+        })
+    }
+}
+
+/*
+ * the states are numbered in the order they are found, the start is 0. The edges of a state are
+ * one per run of chars with the same derivative, and no edge at all for the runs deriving to
+ * Nothing or to a regex that can not reach an accepting one, since the Matcher of a Dfa has to
+ * stop as soon as the match can not go on.
+ */
+pub(crate) fn build(
+    ast: &Ast,
+    max_states: usize,
+) -> Result<Dfa<usize, Action<char>>, ParseErrorKind> {
+    let mut terms = Terms::new();
+    let start = terms.term(ast)?;
+    let mut map = HashMap::from([(start, 0)]);
+    let mut queue = vec![start];
+    let mut edges: Vec<Vec<(Action<char>, usize)>> = Vec::new();
+    let mut top = 0;
+    while top < queue.len() {
+        let re = queue[top];
+        let mut runs: Vec<(char, char, usize)> = Vec::new();
+        for (start, end) in super::runs(terms.ranges(re)).into_iter().map(range) {
+            let derived = terms.derive(re, start);
+            if derived == NOTHING {
+                continue;
+            }
+            let to = match map.get(&derived) {
+                Some(to) => *to,
+                None if queue.len() >= max_states => {
+                    return Err(ParseErrorKind::TooManyStates(max_states))
+                }
+                None => {
+                    map.insert(derived, queue.len());
+                    queue.push(derived);
+                    queue.len() - 1
+                }
+            };
+            match runs.last_mut() {
+                // the run right before goes to the same state, make it one label
+                Some((_, last, state)) if *state == to && next_char(*last) == Some(start) => {
+                    *last = end
+                }
+                _ => runs.push((start, end, to)),
+            }
+        }
+        edges.push(
+            runs.into_iter()
+                .map(|(start, end, to)| (action(start, end), to))
+                .collect(),
+        );
+        top += 1;
+    }
+    // the states from which some accepting state is reachable, walking the edges backwards
+    let mut sources = vec![Vec::new(); queue.len()];
+    for (from, edges) in edges.iter().enumerate() {
+        for &(_, to) in edges {
+            sources[to].push(from);
+        }
+    }
+    let mut live: Vec<bool> = queue.iter().map(|&re| terms.nullable(re)).collect();
+    let mut stack: Vec<usize> = (0..queue.len()).filter(|&state| live[state]).collect();
+    while let Some(state) = stack.pop() {
+        for &from in &sources[state] {
+            if !live[from] {
+                live[from] = true;
+                stack.push(from);
+            }
+        }
+    }
+    let mut dfa = Dfa::new(0);
+    for (from, edges) in edges.into_iter().enumerate() {
+        if terms.nullable(queue[from]) {
+            dfa.add_end_state(from);
+        }
+        for (action, to) in edges {
+            if live[to] {
+                dfa.add_edges(from, action, to).unwrap();
+            }
+        }
+    }
+    Ok(dfa)
+}
+
+#[cfg(test)]
+mod derivative_test {
+    use super::{Id, Terms, ANYTHING, EPSILON, NOTHING};
+    use crate::regex::CharClass;
+
+    fn literal(terms: &mut Terms, c: char) -> Id {
+        terms.class(CharClass::new([(c, c)]))
+    }
+
+    #[test]
+    fn smart_constructors() {
+        let mut terms = Terms::new();
+        let (a, b) = (literal(&mut terms, 'a'), literal(&mut terms, 'b'));
+        let class = terms.class(CharClass::new([('a', 'b')]));
+        assert_eq!(terms.or([b, NOTHING, a, b]), class);
+        assert_eq!(terms.or([a, ANYTHING]), ANYTHING);
+        assert_eq!(terms.and([a, b]), NOTHING);
+        assert_eq!(terms.and([a, ANYTHING]), a);
+        let not = terms.not(a);
+        assert_eq!(terms.not(not), a);
+        let star = terms.star(a);
+        assert_eq!(terms.star(star), star);
+        assert_eq!(terms.concat(EPSILON, a), a);
+        let (ab, ba) = (terms.concat(a, b), terms.concat(b, a));
+        assert_eq!(terms.concat(ab, a), terms.concat(a, ba));
+        let or = terms.or([ab]);
+        assert_eq!(terms.or([ab, ba]), terms.or([ba, or]));
+    }
+    #[test]
+    fn derive() {
+        let mut terms = Terms::new();
+        let (a, b) = (literal(&mut terms, 'a'), literal(&mut terms, 'b'));
+        // (ab)* by a is b(ab)*
+        let ab = terms.concat(a, b);
+        let star = terms.star(ab);
+        let derived = terms.derive(star, 'a');
+        assert_eq!(derived, terms.concat(b, star));
+        assert_eq!(terms.derive(star, 'b'), NOTHING);
+        // ~a by a is ~ε, everything but the empty string
+        let not = terms.not(a);
+        assert!(terms.nullable(not));
+        let derived = terms.derive(not, 'a');
+        assert!(!terms.nullable(derived));
+        let derived = terms.derive(not, 'b');
+        assert!(terms.nullable(derived));
+        let (star, not) = (terms.star(a), terms.not(EPSILON));
+        let and = terms.and([star, not]);
+        assert!(!terms.nullable(and));
+        let derived = terms.derive(and, 'a');
+        assert!(terms.nullable(derived));
+    }
+    #[test]
+    fn repeat() {
+        let mut terms = Terms::new();
+        let a = literal(&mut terms, 'a');
+        // a{2,3} by a is a{1,2}, the counter goes down and nothing is unrolled
+        let mut re = terms.repeat(a, 2, Some(3));
+        for (min, max) in [(1, 2), (0, 1)] {
+            re = terms.derive(re, 'a');
+            assert_eq!(re, terms.repeat(a, min, Some(max)));
+            assert_eq!(terms.nullable(re), min == 0);
+        }
+        assert_eq!(terms.derive(re, 'a'), EPSILON);
+        assert_eq!(terms.repeat(a, 1, Some(1)), a);
+        assert_eq!(terms.repeat(a, 3, Some(0)), EPSILON);
+        assert_eq!(terms.repeat(NOTHING, 2, None), NOTHING);
+        // the empty iterations of a nullable regex fill the min, `(a*){3,}` is `a*`
+        let star = terms.star(a);
+        assert_eq!(terms.repeat(star, 3, None), star);
+        let unbounded = terms.repeat(a, 2, None);
+        let derived = terms.derive(unbounded, 'a');
+        assert_eq!(derived, terms.repeat(a, 1, None));
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    fmt::{self, Display},
+    ops::Range,
+};
+
+use colored::Colorize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    // a ')' without the '(' before it
+    UnbalancedParen,
+    // a '(' never closed
+    UnclosedParen,
+    // '*', '+', '?' or '{' with nothing to repeat
+    DanglingQuantifier,
+    // the pattern ends with a single '\'
+    TrailingBackslash,
+    UnknownEscape,
+    // a '[' never closed
+    UnclosedBracket,
+    // a range like `z-a` in a bracket class
+    InvalidRange,
+    // `\p{...}` naming no known property
+    UnknownProperty,
+    // a `\x` or `\u` escape that is not hex or not a Unicode scalar value
+    InvalidCodePoint,
+    // a '{' not followed by `m}`, `m,}` or `m,n}` with m <= n
+    InvalidRepetition,
+    // a `(?` not followed by flags and then ':' or ')'
+    UnknownFlag,
+    // '~' with nothing to complement
+    DanglingComplement,
+    // `^`, `$`, `\b` or `\B` given to the derivative construction, or under `&` or `~`
+    UnsupportedLook,
+    // building the Nfa would need more states than the limit carried here
+    TooManyStates(usize),
+    // groups, complements and repetitions nested deeper than the limit carried here
+    NestLimitExceeded(usize),
+}
+
+/*
+ * the span is a byte range of the pattern, the error keeps its own copy of the pattern so it can
+ * be shown far away from the place the pattern came from, like a config loader
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Range<usize>,
+    pattern: String,
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnbalancedParen => f.write_str("unbalanced parenthesis"),
+            ParseErrorKind::UnclosedParen => f.write_str("unclosed parenthesis"),
+            ParseErrorKind::DanglingQuantifier => f.write_str("quantifier with nothing to repeat"),
+            ParseErrorKind::TrailingBackslash => f.write_str("trailing backslash"),
+            ParseErrorKind::UnknownEscape => f.write_str("unknown escape sequence"),
+            ParseErrorKind::UnclosedBracket => f.write_str("unclosed character class"),
+            ParseErrorKind::InvalidRange => f.write_str("invalid range in character class"),
+            ParseErrorKind::UnknownProperty => f.write_str("unknown Unicode property"),
+            ParseErrorKind::InvalidCodePoint => f.write_str("invalid code point"),
+            ParseErrorKind::InvalidRepetition => f.write_str("invalid counted repetition"),
+            ParseErrorKind::UnknownFlag => f.write_str("unknown flag"),
+            ParseErrorKind::DanglingComplement => {
+                f.write_str("complement with nothing to complement")
+            }
+            ParseErrorKind::UnsupportedLook => f.write_str(
+                "assertions are not supported under `&` or `~`, nor by the derivative construction",
+            ),
+            ParseErrorKind::TooManyStates(limit) => {
+                write!(f, "the automaton would need more than {} states", limit)
+            }
+            ParseErrorKind::NestLimitExceeded(limit) => {
+                write!(f, "the pattern nests deeper than {} levels", limit)
+            }
+        }
+    }
+}
+
+impl ParseError {
+    #[inline]
+    pub fn new(kind: ParseErrorKind, span: Range<usize>, pattern: &str) -> Self {
+        Self {
+            kind,
+            span,
+            pattern: pattern.to_owned(),
+        }
+    }
+    #[inline]
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+    /*
+     * error: invalid range in character class
+     *   [a-zz-a]
+     *       ^^^
+     */
+    pub fn render(&self, colored: bool) -> String {
+        let column = self.pattern[..self.span.start].chars().count();
+        let width = self.pattern[self.span.clone()].chars().count().max(1);
+        let caret = "^".repeat(width);
+        let (head, caret) = if colored {
+            (
+                format!("{}: {}", "error".red().bold(), self.kind.to_string().bold()),
+                caret.red().bold().to_string(),
+            )
+        } else {
+            (format!("error: {}", self.kind), caret)
+        };
+        format!(
+            "{}\n  {}\n  {}{}",
+            head,
+            self.pattern,
+            " ".repeat(column),
+            caret
+        )
+    }
+}
+
+// `{}` renders the plain text, `{:#}` the colored one
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(f.alternate()))
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod error_test {
+    use super::{ParseError, ParseErrorKind};
+
+    #[test]
+    fn caret_under_span() {
+        let error = ParseError::new(ParseErrorKind::InvalidRange, 4..7, "[a-zz-a]");
+        assert_eq!(
+            error.to_string(),
+            "error: invalid range in character class\n  [a-zz-a]\n      ^^^"
+        );
+        // the caret is placed by chars, not by bytes
+        let error = ParseError::new(ParseErrorKind::UnbalancedParen, 4..5, "éé)");
+        assert_eq!(
+            error.to_string(),
+            "error: unbalanced parenthesis\n  éé)\n    ^"
+        );
+        let error = ParseError::new(ParseErrorKind::TrailingBackslash, 1..2, "a\\");
+        assert!(error.to_string().ends_with("\n   ^"));
+    }
+    #[test]
+    fn colored() {
+        colored::control::set_override(true);
+        let error = ParseError::new(ParseErrorKind::UnclosedParen, 0..1, "(a");
+        let rendered = format!("{:#}", error);
+        assert!(rendered.contains("\u{1b}["));
+        assert!(rendered.contains("(a"));
+        assert_ne!(rendered, error.to_string());
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+
+use super::{boolean, range, runs, unicode, Ast, CharClass, ParseErrorKind};
+use crate::automate::{Action, Dfa, Look, Nfa, StateMachine};
+
+/*
+ * Glushkov construction, also known as the position automaton: every literal, class and
+ * assertion of the pattern is a position and becomes one state, state 0 is the start. An edge
+ * goes from p to q when q may follow p, labeled by what q reads, so there is no epsilon edge at
+ * all and the Nfa has one state per position plus one, where Thompson needs two per node.
+ *
+ * an assertion is a position too, the edges into it are look edges, the only zero width edges
+ * left in the automaton.
+ *
+ * the edges into several positions may leave the same state with overlapping labels, like the
+ * `[a-c]` and the `b` of `[a-c]|b`, they are split on the fly into disjoint labels because two
+ * overlapping Action in one map are the same key.
+ *
+ * `&` and `~` are built as a Dfa by the boolean module, a Dfa reads different chars into the same
+ * state, so its positions are its pairs of states with an edge between them, reading the labels
+ * of those edges, and a pair follows the pairs ending where it starts.
+ */
+pub(crate) struct Glushkov {
+    positions: Vec<Position>,
+    // indexed by state, follow[0] is where the start goes
+    follow: Vec<Vec<usize>>,
+    max_states: usize,
+    words: Option<CharClass>,
+}
+
+enum Position {
+    Class(CharClass),
+    Look(Look),
+}
+
+// a sub expression seen from the outside
+#[derive(Default)]
+struct Summary {
+    nullable: bool,
+    first: Vec<usize>,
+    last: Vec<usize>,
+}
+
+type Positions = Result<Summary, ParseErrorKind>;
+
+impl Glushkov {
+    pub fn build(ast: &Ast, max_states: usize) -> Result<Nfa<usize, Action<char>>, ParseErrorKind> {
+        let mut glushkov = Self {
+            positions: Vec::new(),
+            follow: vec![Vec::new()],
+            max_states,
+            words: ast.has_word_boundary().then(|| unicode::perl('w').unwrap()),
+        };
+        let summary = glushkov.compile(ast)?;
+        glushkov.follow[0] = summary.first;
+        let mut nfa = Nfa::new(0);
+        for (from, targets) in glushkov.follow.iter().enumerate() {
+            nfa.add_states(from);
+            glushkov.edges(&mut nfa, from, targets);
+        }
+        if summary.nullable {
+            nfa.add_end_state(0);
+        }
+        for state in summary.last {
+            nfa.add_end_state(state);
+        }
+        Ok(nfa)
+    }
+    #[inline]
+    fn position(&mut self, position: Position) -> Positions {
+        let state = self.positions.len() + 1;
+        if state >= self.max_states {
+            return Err(ParseErrorKind::TooManyStates(self.max_states));
+        }
+        self.positions.push(position);
+        self.follow.push(Vec::new());
+        Ok(Summary {
+            nullable: false,
+            first: vec![state],
+            last: vec![state],
+        })
+    }
+    #[inline]
+    fn link(&mut self, from: &[usize], to: &[usize]) {
+        for &p in from {
+            for &q in to {
+                if !self.follow[p].contains(&q) {
+                    self.follow[p].push(q);
+                }
+            }
+        }
+    }
+    // `summary` followed by `next`
+    fn then(&mut self, summary: &mut Summary, next: Summary) {
+        self.link(&summary.last, &next.first);
+        if summary.nullable {
+            summary.first.extend_from_slice(&next.first);
+        }
+        if next.nullable {
+            summary.last.extend(next.last);
+        } else {
+            summary.last = next.last;
+        }
+        summary.nullable &= next.nullable;
+    }
+    fn compile(&mut self, ast: &Ast) -> Positions {
+        match ast {
+            Ast::Empty => Ok(Summary {
+                nullable: true,
+                ..Default::default()
+            }),
+            Ast::Literal(c) => self.position(Position::Class(CharClass::new([(*c, *c)]))),
+            Ast::Class(class) => self.position(Position::Class(class.clone())),
+            Ast::Look(look) => self.position(Position::Look(*look)),
+            Ast::Concat(items) => {
+                let mut summary = self.compile(&Ast::Empty)?;
+                for item in items {
+                    let next = self.compile(item)?;
+                    self.then(&mut summary, next);
+                }
+                Ok(summary)
+            }
+            Ast::Alternation(branches) => {
+                let mut summary = Summary::default();
+                for branch in branches {
+                    let next = self.compile(branch)?;
+                    summary.nullable |= next.nullable;
+                    summary.first.extend(next.first);
+                    summary.last.extend(next.last);
+                }
+                Ok(summary)
+            }
+            Ast::Star(ast) => {
+                let mut summary = self.compile(ast)?;
+                self.link(&summary.last, &summary.first);
+                summary.nullable = true;
+                Ok(summary)
+            }
+            Ast::Plus(ast) => {
+                let summary = self.compile(ast)?;
+                self.link(&summary.last, &summary.first);
+                Ok(summary)
+            }
+            Ast::Optional(ast) => {
+                let mut summary = self.compile(ast)?;
+                summary.nullable = true;
+                Ok(summary)
+            }
+            Ast::Repeat { ast, min, max } => self.repeat(ast, *min, *max),
+            // a position has no room for a capture, a group only groups here
+            Ast::Capture(_, ast) => self.compile(ast),
+            Ast::Intersection(_) | Ast::Complement(_) => {
+                let dfa = boolean::compile(ast, self.max_states, self.words.as_ref())?;
+                self.embed(&dfa)
+            }
+        }
+    }
+    fn embed(&mut self, dfa: &Dfa<usize, Action<char>>) -> Positions {
+        let mut pairs = BTreeMap::new();
+        for (pair, class) in boolean::labels(dfa) {
+            let position = self.position(Position::Class(class))?;
+            pairs.insert(pair, position.first[0]);
+        }
+        let mut summary = Summary {
+            nullable: dfa.is_end(&dfa.start_state()),
+            ..Default::default()
+        };
+        for (&(from, to), &position) in pairs.iter() {
+            if from == dfa.start_state() {
+                summary.first.push(position);
+            }
+            if dfa.is_end(&to) {
+                summary.last.push(position);
+            }
+            let next: Vec<usize> = pairs
+                .range((to, 0)..=(to, usize::MAX))
+                .map(|(_, next)| *next)
+                .collect();
+            self.link(&[position], &next);
+        }
+        Ok(summary)
+    }
+    /*
+     * `a{2,4}` is `aa(a(a)?)?`, an optional copy only follows the copy right before it, so no
+     * input can be split between the optional copies in more than one way
+     */
+    fn repeat(&mut self, ast: &Ast, min: u32, max: Option<u32>) -> Positions {
+        let mut summary = self.compile(&Ast::Empty)?;
+        for _ in 0..min {
+            let next = self.compile(ast)?;
+            // a copy without positions gives the same summary every time, like `(?:){4294967295}`
+            let empty = next.first.is_empty() && next.last.is_empty();
+            self.then(&mut summary, next);
+            if empty {
+                break;
+            }
+        }
+        match max {
+            None => {
+                let next = self.compile(&Ast::Star(Box::new(ast.clone())))?;
+                self.then(&mut summary, next);
+            }
+            Some(max) => {
+                let mut previous = summary.last.clone();
+                let mut reaches_first = summary.nullable;
+                for _ in min..max {
+                    let copy = self.compile(ast)?;
+                    if copy.first.is_empty() && copy.last.is_empty() {
+                        break;
+                    }
+                    self.link(&previous, &copy.first);
+                    if reaches_first {
+                        summary.first.extend_from_slice(&copy.first);
+                    }
+                    reaches_first &= copy.nullable;
+                    summary.last.extend_from_slice(&copy.last);
+                    if copy.nullable {
+                        previous.extend(copy.last);
+                    } else {
+                        previous = copy.last;
+                    }
+                }
+            }
+        }
+        Ok(summary)
+    }
+    // the edges from `from` into every position of `targets`, cut into the runs of their classes
+    fn edges(&self, nfa: &mut Nfa<usize, Action<char>>, from: usize, targets: &[usize]) {
+        let mut classes = Vec::with_capacity(targets.len());
+        for &to in targets {
+            match &self.positions[to - 1] {
+                Position::Class(class) => classes.push((class, to)),
+                Position::Look(look) => nfa.add_look(from, *look, to),
+            }
+        }
+        let ranges = classes
+            .iter()
+            .map(|(class, _)| *class)
+            .chain(self.words.as_ref())
+            .flat_map(|class| class.ranges().iter().copied());
+        for run in runs(ranges) {
+            let start = range(run).0;
+            for (class, to) in classes.iter() {
+                if class.contains(start) {
+                    nfa.add_edges(from, Some(run), *to);
+                }
+            }
+        }
+    }
+}
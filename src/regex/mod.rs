@@ -0,0 +1,746 @@
+mod ast;
+mod boolean;
+mod class;
+mod derivative;
+mod error;
+mod glushkov;
+mod parser;
+mod thompson;
+mod unicode;
+mod unicode_tables;
+pub use ast::Ast;
+pub use class::CharClass;
+pub(crate) use class::{action, next_char, prev_char, range, runs};
+pub use error::*;
+use glushkov::Glushkov;
+use parser::Parser;
+use thompson::Thompson;
+pub(crate) use unicode::is_word_char;
+
+use crate::automate::{Action, Dfa, Nfa};
+
+// large enough for `\w{1,1000}`, small enough to fail fast on `(a{1000}){1000}`
+pub const DEFAULT_MAX_STATES: usize = 100_000;
+// the parser and every construction recurse once per level, this keeps them far from the stack end
+pub const DEFAULT_NEST_LIMIT: usize = 250;
+
+/*
+ * compile a pattern into a Nfa, supported syntax:
+ *  - concatenation `ab`, alternation `a|b` and capture groups `(ab)`, their spans are found by
+ *    running automate::PikeVm over the Nfa of the Thompson construction
+ *  - repetition `a*`, `a+`, `a?` and counted repetition `a{m}`, `a{m,}`, `a{m,n}`
+ *  - any char but the line feed `.`, classes `[a-z0-9_]` and negated classes `[^...]`
+ *  - escapes of the meta characters `\|\*\+\?\(\)\.\[\]\\` and `\n`, `\t`, `\r`
+ *  - code points `\x41`, `\x{41}`, `\u{1F600}`
+ *  - Unicode general categories `\p{L}`, `\pL`, `\p{Decimal_Number}`, negated as `\P{L}`
+ *  - `\d`, `\w`, `\s` and their negations `\D`, `\W`, `\S`, all of them Unicode aware
+ *  - the zero width assertions `^`, `$`, `\b` and `\B`, as look edges of the Nfa
+ *  - intersection `a&b` and complement `~a`, `&` binds tighter than `|` and looser than
+ *    concatenation, `~` applies to the repetition after it, so `~ab*&c` is `((~a)b*)&c`. They are
+ *    built as Dfa, their operands can not hold assertions
+ *  - non capturing groups `(?:ab)`, case insensitivity `(?i)ab`, `(?i:a)b` and `(?-i)`, using
+ *    the simple case folding of Unicode so `(?i)k` also matches the Kelvin sign
+ *
+ * the Unicode tables are generated into the crate by scripts/unicode_tables.py
+ */
+// the pattern as the tree the automaton constructions start from, see Ast for printing it back
+#[inline]
+pub fn parse_ast(pattern: &str) -> Result<Ast, ParseError> {
+    RegexBuilder::new().parse_ast(pattern)
+}
+
+#[inline]
+pub fn parse(pattern: &str) -> Result<Nfa<usize, Action<char>>, ParseError> {
+    RegexBuilder::new().build(pattern)
+}
+
+/*
+ * how the AST becomes a Nfa, both give the same language:
+ *  - Thompson, two states per node glued by epsilon edges, the size grows with the pattern
+ *  - Glushkov, one state per literal or class and no epsilon edge, the closure of a state is the
+ *    state itself, but a state has an edge for each position that may follow it, so the edges
+ *    can grow with the square of the pattern, like `(a|b|c|d)*`
+ *
+ * only Thompson records the capture groups, a Glushkov Nfa has no slots: a group only groups
+ * there and automate::PikeVm reports the span of the whole match, the group 0, and nothing else
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Construction {
+    #[default]
+    Thompson,
+    Glushkov,
+}
+
+#[derive(Debug, Clone)]
+pub struct RegexBuilder {
+    max_states: usize,
+    nest_limit: usize,
+    case_insensitive: bool,
+    construction: Construction,
+}
+
+impl Default for RegexBuilder {
+    fn default() -> Self {
+        Self {
+            max_states: DEFAULT_MAX_STATES,
+            nest_limit: DEFAULT_NEST_LIMIT,
+            case_insensitive: false,
+            construction: Construction::default(),
+        }
+    }
+}
+
+impl RegexBuilder {
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+    // the construction fails with ParseErrorKind::TooManyStates instead of growing past the limit
+    #[inline]
+    pub fn max_states(mut self, limit: usize) -> Self {
+        self.max_states = limit;
+        self
+    }
+    /*
+     * the deepest the tree of the pattern may be, counting every group, complement, repetition,
+     * alternation and concatenation on the way down, past it the parser fails with
+     * ParseErrorKind::NestLimitExceeded
+     */
+    #[inline]
+    pub fn nest_limit(mut self, limit: usize) -> Self {
+        self.nest_limit = limit;
+        self
+    }
+    // same as starting the pattern with `(?i)`, a `(?-i)` in the pattern still turns it off
+    #[inline]
+    pub fn case_insensitive(mut self, yes: bool) -> Self {
+        self.case_insensitive = yes;
+        self
+    }
+    // Construction::Glushkov drops the capture groups, see Construction
+    #[inline]
+    pub fn construction(mut self, construction: Construction) -> Self {
+        self.construction = construction;
+        self
+    }
+    #[inline]
+    pub fn parse_ast(&self, pattern: &str) -> Result<Ast, ParseError> {
+        Parser::new(pattern)
+            .case_insensitive(self.case_insensitive)
+            .nest_limit(self.nest_limit)
+            .parse()
+    }
+    pub fn build(&self, pattern: &str) -> Result<Nfa<usize, Action<char>>, ParseError> {
+        let ast = self.parse_ast(pattern)?;
+        // the limit is about the whole pattern, not any part of it
+        match self.construction {
+            Construction::Thompson => Thompson::build(&ast, self.max_states),
+            Construction::Glushkov => Glushkov::build(&ast, self.max_states),
+        }
+        .map_err(|kind| ParseError::new(kind, 0..pattern.len(), pattern))
+    }
+    /*
+     * the Dfa straight from the pattern by Brzozowski derivatives, without any Nfa, the states
+     * are counted against `max_states` and the construction choice does not apply. The pattern can
+     * not contain `^`, `$`, `\b` or `\B`.
+     */
+    pub fn build_dfa(&self, pattern: &str) -> Result<Dfa<usize, Action<char>>, ParseError> {
+        let ast = self.parse_ast(pattern)?;
+        derivative::build(&ast, self.max_states)
+            .map_err(|kind| ParseError::new(kind, 0..pattern.len(), pattern))
+    }
+}
+
+#[cfg(test)]
+mod regex_test {
+    use std::collections::BTreeSet;
+
+    use super::{parse, Construction, ParseErrorKind, RegexBuilder};
+    use crate::automate::{Action, Dfa, Nfa, PikeVm, StateMachine};
+    use crate::matches::{Input, Matcher};
+
+    // walk the epsilon edges by hand, the Nfa matcher is not the thing under test here
+    fn closure(nfa: &Nfa<usize, Action<char>>, set: BTreeSet<usize>) -> BTreeSet<usize> {
+        let mut result = set.clone();
+        let mut stack: Vec<_> = set.into_iter().collect();
+        while let Some(state) = stack.pop() {
+            for next in nfa.next_state(&state, &None).into_iter().flatten() {
+                if result.insert(*next) {
+                    stack.push(*next);
+                }
+            }
+        }
+        result
+    }
+
+    fn accepts(nfa: &Nfa<usize, Action<char>>, s: &str) -> bool {
+        let mut set = closure(nfa, nfa.start_states.clone());
+        for c in s.chars() {
+            let moved = set
+                .iter()
+                .filter_map(|state| nfa.next_state(state, &Some(Action::Single(c))))
+                .flatten()
+                .copied()
+                .collect();
+            set = closure(nfa, moved);
+        }
+        set.iter().any(|state| nfa.is_end(state))
+    }
+
+    fn dfa_match(pattern: &str, s: &str) -> Option<String> {
+        let dfa = Dfa::from(&parse(pattern).unwrap());
+        dfa.r#match(&mut s.chars().map(Action::Single))
+            .map(|matched| {
+                matched
+                    .into_iter()
+                    .map(|action| match action {
+                        Action::Single(c) => c,
+                        Action::Range(..) => unreachable!(),
+                    })
+                    .collect()
+            })
+    }
+
+    #[test]
+    fn thompson_fragment() {
+        let nfa = parse("a").unwrap();
+        assert_eq!(nfa.all_state.len(), 2);
+        assert_eq!(nfa.path_len(), 1);
+        let nfa = parse("a|b").unwrap();
+        assert_eq!(nfa.all_state.len(), 6);
+        assert_eq!(nfa.path_len(), 2);
+    }
+    #[test]
+    fn concat_and_alternation() {
+        let nfa = parse("ab|cd").unwrap();
+        assert!(accepts(&nfa, "ab"));
+        assert!(accepts(&nfa, "cd"));
+        assert!(!accepts(&nfa, "abcd"));
+        assert!(!accepts(&nfa, "a"));
+        assert!(!accepts(&nfa, ""));
+    }
+    #[test]
+    fn repetition() {
+        let nfa = parse("a(bc)*d+e?").unwrap();
+        assert!(accepts(&nfa, "ad"));
+        assert!(accepts(&nfa, "abcbcdde"));
+        assert!(!accepts(&nfa, "abd"));
+        assert!(!accepts(&nfa, "a"));
+        assert!(!accepts(&nfa, "adee"));
+        let nfa = parse("(a|)*").unwrap();
+        assert!(accepts(&nfa, ""));
+        assert!(accepts(&nfa, "aaa"));
+    }
+    #[test]
+    fn escapes() {
+        let nfa = parse(r"\(\*\)\\\n").unwrap();
+        assert!(accepts(&nfa, "(*)\\\n"));
+        assert!(!accepts(&nfa, "*"));
+    }
+    #[test]
+    fn bracket_class() {
+        let nfa = parse("[a-z0-9_]+").unwrap();
+        // one label per range, not one per char
+        assert_eq!(nfa.path_len(), 3);
+        assert!(accepts(&nfa, "snake_case_42"));
+        assert!(!accepts(&nfa, "CamelCase"));
+        let nfa = parse(r"[]\-^]").unwrap();
+        assert!(accepts(&nfa, "]"));
+        assert!(accepts(&nfa, "-"));
+        assert!(accepts(&nfa, "^"));
+        assert!(!accepts(&nfa, "\\"));
+        let nfa = parse("[a-]").unwrap();
+        assert!(accepts(&nfa, "-"));
+    }
+    #[test]
+    fn negated_class_and_dot() {
+        let nfa = parse("[^a-z]").unwrap();
+        assert_eq!(nfa.path_len(), 2);
+        assert!(accepts(&nfa, "A"));
+        assert!(accepts(&nfa, "\u{10FFFF}"));
+        assert!(!accepts(&nfa, "q"));
+        let nfa = parse("a.c").unwrap();
+        assert!(accepts(&nfa, "abc"));
+        assert!(accepts(&nfa, "a\u{E000}c"));
+        assert!(!accepts(&nfa, "a\nc"));
+        let nfa = parse(r"a\.c").unwrap();
+        assert!(!accepts(&nfa, "abc"));
+        assert!(accepts(&nfa, "a.c"));
+    }
+    #[test]
+    fn unicode_classes() {
+        let nfa = parse(r"\p{L}[\p{L}\d_]*").unwrap();
+        for s in ["x1", "имя_2", "名前", "ιδ٣"] {
+            assert!(accepts(&nfa, s), "{}", s);
+        }
+        assert!(!accepts(&nfa, "1x"));
+        assert!(!accepts(&nfa, "a-b"));
+        let nfa = parse(r"\w+\s\W").unwrap();
+        assert!(accepts(&nfa, "héllo\u{3000}!"));
+        assert!(!accepts(&nfa, "hello a"));
+        let nfa = parse(r"\PL\P{Nd}").unwrap();
+        assert!(accepts(&nfa, "1a"));
+        assert!(!accepts(&nfa, "a1"));
+    }
+    #[test]
+    fn code_point_escapes() {
+        let nfa = parse(r"\x41\x{42}\u{1F600}[\x30-\u{39}]").unwrap();
+        assert!(accepts(&nfa, "AB😀5"));
+        assert!(!accepts(&nfa, "AB😀a"));
+    }
+    #[test]
+    fn counted_repetition() {
+        let nfa = parse("[0-9]{1,3}").unwrap();
+        assert!(!accepts(&nfa, ""));
+        assert!(accepts(&nfa, "1"));
+        assert!(accepts(&nfa, "255"));
+        assert!(!accepts(&nfa, "1024"));
+        let nfa = parse("(ab){2}").unwrap();
+        assert!(accepts(&nfa, "abab"));
+        assert!(!accepts(&nfa, "ab"));
+        assert!(!accepts(&nfa, "ababab"));
+        let nfa = parse("a{2,}b{0}").unwrap();
+        assert!(!accepts(&nfa, "a"));
+        assert!(accepts(&nfa, "aaaaa"));
+        assert!(!accepts(&nfa, "aab"));
+        let nfa = parse(r"\{a\}").unwrap();
+        assert!(accepts(&nfa, "{a}"));
+    }
+    #[test]
+    fn state_limit() {
+        assert!(parse("[0-9]{1,255}").is_ok());
+        let error = parse("(a{1000}){1000}").unwrap_err();
+        assert_eq!(
+            error.kind,
+            ParseErrorKind::TooManyStates(super::DEFAULT_MAX_STATES)
+        );
+        assert_eq!(error.span, 0..15);
+        let builder = RegexBuilder::new().max_states(8);
+        assert!(builder.build("a{3}").is_ok());
+        assert_eq!(
+            builder.build("a{4}").unwrap_err().kind,
+            ParseErrorKind::TooManyStates(8)
+        );
+        for construction in [Construction::Thompson, Construction::Glushkov] {
+            let builder = RegexBuilder::new().construction(construction);
+            assert_eq!(
+                builder.build("a{0,4294967295}").unwrap_err().kind,
+                ParseErrorKind::TooManyStates(super::DEFAULT_MAX_STATES)
+            );
+        }
+        // the copies of an empty group add no position, they are not all walked through
+        let glushkov = RegexBuilder::new().construction(Construction::Glushkov);
+        assert!(glushkov.build("(?:){0,4294967295}").is_ok());
+        assert!(glushkov.build("(?:){4294967295}").is_ok());
+        // the derivatives count a repeat down instead of unrolling it
+        let dfa = RegexBuilder::new().build_dfa("a{5000}").unwrap();
+        let input = "a".repeat(5001);
+        let matched = dfa.r#match(&mut input.chars().map(Action::Single));
+        assert_eq!(matched.map(|matched| matched.len()), Some(5000));
+        assert_eq!(
+            RegexBuilder::new()
+                .build_dfa("a{0,4294967295}")
+                .unwrap_err()
+                .kind,
+            ParseErrorKind::TooManyStates(super::DEFAULT_MAX_STATES)
+        );
+    }
+    #[test]
+    fn anchors() {
+        assert_eq!(dfa_match("^ab", "ab").as_deref(), Some("ab"));
+        assert_eq!(dfa_match("^ab", "xab"), None);
+        assert_eq!(dfa_match("ab", "xab").as_deref(), Some("ab"));
+        assert_eq!(dfa_match("ab$", "xab").as_deref(), Some("ab"));
+        assert_eq!(dfa_match("ab$", "abc"), None);
+        assert_eq!(dfa_match("^$", ""), None);
+    }
+    #[test]
+    fn word_boundary() {
+        assert_eq!(dfa_match(r"\bfoo\b", "a foo b").as_deref(), Some("foo"));
+        assert_eq!(dfa_match(r"\bfoo\b", "foo").as_deref(), Some("foo"));
+        assert_eq!(dfa_match(r"\bfoo\b", "foobar"), None);
+        assert_eq!(dfa_match(r"\bfoo\b", "xfoo"), None);
+        assert_eq!(dfa_match(r"\bfoo\b", "(foo)").as_deref(), Some("foo"));
+        assert_eq!(dfa_match(r"a\Bb", "ab").as_deref(), Some("ab"));
+        assert_eq!(dfa_match(r"\b[a-c]+\b", "x abc").as_deref(), Some("abc"));
+        assert_eq!(dfa_match(r"\b[a-c]+\b", "xabc"), None);
+        assert_eq!(dfa_match(r"\B[a-c]+", "xabc").as_deref(), Some("abc"));
+    }
+    #[test]
+    fn case_insensitive() {
+        let nfa = parse("(?i)abc").unwrap();
+        assert!(accepts(&nfa, "AbC"));
+        assert!(accepts(&nfa, "abc"));
+        let nfa = parse("a(?i:b)c").unwrap();
+        assert!(accepts(&nfa, "aBc"));
+        assert!(!accepts(&nfa, "ABc"));
+        assert!(!accepts(&nfa, "abC"));
+        // the flag lasts until the end of the enclosing group
+        let nfa = parse("(a(?i)b)c").unwrap();
+        assert!(accepts(&nfa, "aBc"));
+        assert!(!accepts(&nfa, "aBC"));
+        let nfa = parse("(?i)a(?-i)b").unwrap();
+        assert!(accepts(&nfa, "Ab"));
+        assert!(!accepts(&nfa, "AB"));
+        let nfa = parse("(?i)[a-c]+").unwrap();
+        assert!(accepts(&nfa, "aBC"));
+        assert!(!accepts(&nfa, "D"));
+        let nfa = parse(r"(?i)\p{Lu}").unwrap();
+        assert!(accepts(&nfa, "a"));
+        // simple case folding goes beyond ASCII
+        let nfa = parse("(?i)k").unwrap();
+        assert!(accepts(&nfa, "\u{212A}"));
+        assert!(accepts(&nfa, "K"));
+        let nfa = parse("(?i)σ").unwrap();
+        assert!(accepts(&nfa, "Σ"));
+        assert!(accepts(&nfa, "ς"));
+        // the negation comes after the folding, so `a` and `A` are both left out
+        let nfa = parse("(?i)[^a]").unwrap();
+        assert!(!accepts(&nfa, "a"));
+        assert!(!accepts(&nfa, "A"));
+        assert!(accepts(&nfa, "b"));
+        let nfa = parse("(?:ab)+").unwrap();
+        assert!(accepts(&nfa, "abab"));
+        assert!(!accepts(&nfa, "ABAB"));
+        let builder = RegexBuilder::new().case_insensitive(true);
+        assert!(accepts(&builder.build("ab").unwrap(), "AB"));
+        assert!(!accepts(&builder.build("a(?-i)b").unwrap(), "AB"));
+        assert_eq!(
+            dfa_match("(?i)hello", "say HeLLo").as_deref(),
+            Some("HeLLo")
+        );
+    }
+    #[test]
+    fn glushkov() {
+        let glushkov = RegexBuilder::new().construction(Construction::Glushkov);
+        let patterns = [
+            "(a|b)*abb",
+            "a{2,4}b?",
+            "(ab|c)+d{0,2}",
+            "[a-c]|b",
+            "(a*)*b",
+            r"\d+(\.\d+)?",
+            "",
+            "x(?i)yz|.",
+        ];
+        let inputs = [
+            "", "abb", "babb", "aab", "aaaab", "aaaaab", "ababcdd", "cddd", "b", "c", "aab",
+            "12.5", "12.", "xYZ", "z", "zz",
+        ];
+        for pattern in patterns {
+            let thompson = parse(pattern).unwrap();
+            let nfa = glushkov.build(pattern).unwrap();
+            // one state per position and the start, without any epsilon edge
+            assert!(nfa.all_state.len() < thompson.all_state.len());
+            for state in nfa.all_state.iter() {
+                assert_eq!(closure(&nfa, [*state].into()), [*state].into());
+            }
+            for input in inputs {
+                assert_eq!(
+                    accepts(&nfa, input),
+                    accepts(&thompson, input),
+                    "{} on {:?}",
+                    pattern,
+                    input
+                );
+            }
+        }
+        assert_eq!(glushkov.build("(a|b)*abb").unwrap().all_state.len(), 6);
+        // the `b` of `[a-c]` and the `b` literal leave the start by the same label
+        let nfa = glushkov.build("[a-c]|b").unwrap();
+        assert_eq!(
+            (&nfa).next_state(&0, &Some(Action::Single('b'))),
+            Some(&[1, 2].into())
+        );
+        assert_eq!(
+            (&nfa).next_state(&0, &Some(Action::Single('a'))),
+            Some(&[1].into())
+        );
+        assert_eq!(
+            glushkov
+                .clone()
+                .max_states(4)
+                .build("a{4}")
+                .unwrap_err()
+                .kind,
+            ParseErrorKind::TooManyStates(4)
+        );
+        // the determinization runs on the smaller Nfa and still finds the same matches
+        let dfa = Dfa::from(&glushkov.build(r"\bfoo\b|^x$").unwrap());
+        for (input, expected) in [("a foo b", Some("foo")), ("foobar", None), ("x", Some("x"))] {
+            let matched = dfa.r#match(&mut input.chars().map(Action::Single));
+            assert_eq!(matched.map(|matched| matched.len()), expected.map(str::len));
+        }
+    }
+    #[test]
+    fn derivative() {
+        let builder = RegexBuilder::new();
+        let patterns = [
+            "(a|b)*abb",
+            "a{2,4}b?",
+            "(ab|c)+d{0,2}",
+            "[a-c]|b",
+            "(a*)*b",
+            r"\d+(\.\d+)?",
+            "x(?i:yz)|[0-9]",
+            "[a-z]*ing|p[a-o]ng",
+            "[a-c]+|b[0-9]?",
+        ];
+        let inputs = [
+            "abb",
+            "xbabbx",
+            "aab",
+            "aaaab",
+            "aaaaab",
+            "ababcdd",
+            "cddd",
+            "b",
+            "12.5",
+            "12.",
+            "xYZ",
+            "z",
+            "going",
+            "ping pong",
+        ];
+        for pattern in patterns {
+            let dfa = builder.build_dfa(pattern).unwrap();
+            let nfa = Dfa::from(&parse(pattern).unwrap());
+            for input in inputs {
+                assert_eq!(
+                    dfa.r#match(&mut input.chars().map(Action::Single)),
+                    nfa.r#match(&mut input.chars().map(Action::Single)),
+                    "{} on {:?}",
+                    pattern,
+                    input
+                );
+            }
+        }
+        assert_eq!(
+            builder.build_dfa("^a").unwrap_err().kind,
+            ParseErrorKind::UnsupportedLook
+        );
+        assert_eq!(
+            builder
+                .clone()
+                .max_states(3)
+                .build_dfa("abc")
+                .unwrap_err()
+                .kind,
+            ParseErrorKind::TooManyStates(3)
+        );
+        assert!(builder.clone().max_states(4).build_dfa("abc").is_ok());
+    }
+    #[test]
+    fn intersection_and_complement() {
+        let glushkov = RegexBuilder::new().construction(Construction::Glushkov);
+        let cases: &[(&str, &[&str], &[&str])] = &[
+            (
+                "~(.*secret.*)&[a-z]+",
+                &["hello", "secre", "ecrets"],
+                &["", "mysecretword", "secret", "Hello"],
+            ),
+            ("~a", &["", "b", "aa", "\n"], &["a"]),
+            ("~(a*)", &["b", "ab"], &["", "aaa"]),
+            ("a+&~(aa)", &["a", "aaa"], &["", "aa"]),
+            (
+                "[a-c]+&.*b.*&~(.*c)",
+                &["b", "abba", "cba"],
+                &["ac", "aaa", "bc"],
+            ),
+            ("x(a&b)y|z", &["z"], &["xy", "xay"]),
+            ("(~a)b", &["b", "bb", "aab"], &["ab"]),
+            ("\\&\\~[&~]", &["&~&", "&~~"], &["&~"]),
+        ];
+        for (pattern, matched, unmatched) in cases {
+            let nfas = [parse(pattern).unwrap(), glushkov.build(pattern).unwrap()];
+            for nfa in nfas.iter() {
+                for input in matched.iter() {
+                    assert!(accepts(nfa, input), "{} on {:?}", pattern, input);
+                }
+                for input in unmatched.iter() {
+                    assert!(!accepts(nfa, input), "{} on {:?}", pattern, input);
+                }
+            }
+        }
+        // the whole pattern ends up as one Dfa, by derivatives or by the subset construction
+        let dfas = [
+            RegexBuilder::new()
+                .build_dfa("~(.*secret.*)&[a-z]+")
+                .unwrap(),
+            Dfa::from(&parse("~(.*secret.*)&[a-z]+").unwrap()),
+        ];
+        for dfa in dfas.iter() {
+            let dfa_accepts = |s: &str| {
+                let mut state = Some(dfa.start_state());
+                for c in s.chars() {
+                    state = state.and_then(|state| dfa.next_state(&state, &Action::Single(c)));
+                }
+                state.is_some_and(|state| dfa.is_end(&state))
+            };
+            assert!(dfa_accepts("hello"));
+            assert!(dfa_accepts("secre"));
+            assert!(!dfa_accepts("secret"));
+            assert!(!dfa_accepts("asecrets"));
+            assert!(!dfa_accepts("hello world"));
+        }
+        assert_eq!(
+            dfa_match(r"\b(~(.*x.*)&[a-z]+)", "abc").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            parse("~(^a)").unwrap_err().kind,
+            ParseErrorKind::UnsupportedLook
+        );
+        assert_eq!(
+            parse("a&\\b").unwrap_err().kind,
+            ParseErrorKind::UnsupportedLook
+        );
+        // 101 states for each operand, about 10000 for their product
+        let builder = RegexBuilder::new().max_states(2000);
+        let pattern = "(?:b*ab*){100}&(?:a*ba*){100}";
+        assert_eq!(
+            builder.build(pattern).unwrap_err().kind,
+            ParseErrorKind::TooManyStates(2000)
+        );
+        RegexBuilder::new()
+            .max_states(50_000)
+            .build(pattern)
+            .unwrap();
+    }
+    #[test]
+    fn nfa_matcher() {
+        let span = |pattern, s: &str| {
+            let nfa = parse(pattern).unwrap();
+            nfa.find(&mut Input::new(s.chars().map(Action::Single)))
+        };
+        // leftmost first, then longest
+        assert_eq!(span("abcd|bc", "xabcd"), Some(1..5));
+        assert_eq!(span("bc|abcd", "xabce"), Some(2..4));
+        assert_eq!(span("a+", "baaab"), Some(1..4));
+        assert_eq!(span("a*", "bbb"), Some(0..0));
+        assert_eq!(span("x", "abc"), None);
+        assert_eq!(span(r"\bfoo\b", "foobar a foo"), Some(9..12));
+        assert_eq!(span("^a|b$", "aab"), Some(0..1));
+        assert_eq!(span("b$", "bab"), Some(2..3));
+        assert_eq!(span("(?i)straße", "STRASSE Straẞe"), Some(8..14));
+        // no backtracking, a long input takes no more than a pass over it
+        let input = "a".repeat(5000);
+        assert_eq!(span("(a|aa)*b", &input), None);
+        assert_eq!(span("(a|aa)*", &input), Some(0..5000));
+        assert_eq!(span("(a*)*(a*)*c|a", &input), Some(0..1));
+        // the next match sees the symbol before it for `\b`, the spans count from the input start
+        let nfa = parse(r"\bab").unwrap();
+        let mut input = Input::new("abab ab".chars().map(Action::Single));
+        assert_eq!(
+            std::iter::from_fn(|| nfa.find(&mut input)).collect::<Vec<_>>(),
+            vec![0..2, 5..7]
+        );
+    }
+    #[test]
+    fn captures() {
+        let captures = |pattern, s: &str| {
+            let nfa = parse(pattern).unwrap();
+            PikeVm::new(&nfa)
+                .r#match(&mut Input::new(s.chars().map(Action::Single)))
+                .map(|captures| captures.iter().collect::<Vec<_>>())
+        };
+        assert_eq!(
+            captures(r"(\d+)-(\d+)", "tel 12-345"),
+            Some(vec![Some(4..10), Some(4..6), Some(7..10)])
+        );
+        // the first branch and the greedy loop win, the groups are numbered by their `(`
+        assert_eq!(
+            captures("(a|ab)(c|bcd)(d*)", "abcd"),
+            Some(vec![Some(0..4), Some(0..1), Some(1..4), Some(4..4)])
+        );
+        assert_eq!(
+            captures("((a)|b)+", "ab"),
+            Some(vec![Some(0..2), Some(1..2), Some(0..1)])
+        );
+        assert_eq!(captures("(a)|b", "b"), Some(vec![Some(0..1), None]));
+        assert_eq!(
+            captures("(?:x(y))?(z{2})", "zz"),
+            Some(vec![Some(0..2), None, Some(0..2)])
+        );
+        assert_eq!(
+            captures(r"\b(\w+)\b", "-- word"),
+            Some(vec![Some(3..7), Some(3..7)])
+        );
+        assert_eq!(captures("(a)", "bbb"), None);
+        // the Glushkov construction has no room for the slots, only the whole match is there
+        let nfa = RegexBuilder::new()
+            .construction(Construction::Glushkov)
+            .build("(a)b")
+            .unwrap();
+        let captures = PikeVm::new(&nfa).r#match(&mut Input::new("ab".chars().map(Action::Single)));
+        assert_eq!(captures.map(|c| c.groups()), Some(1));
+    }
+    #[test]
+    fn parse_error() {
+        let error = |pattern| {
+            let error = parse(pattern).unwrap_err();
+            (error.kind, error.span)
+        };
+        assert_eq!(error("(ab"), (ParseErrorKind::UnclosedParen, 0..1));
+        assert_eq!(error("ab)"), (ParseErrorKind::UnbalancedParen, 2..3));
+        assert_eq!(error("a|*"), (ParseErrorKind::DanglingQuantifier, 2..3));
+        assert_eq!(error("{2}"), (ParseErrorKind::DanglingQuantifier, 0..1));
+        assert_eq!(error("ab\\"), (ParseErrorKind::TrailingBackslash, 2..3));
+        assert_eq!(error("\\q"), (ParseErrorKind::UnknownEscape, 0..2));
+        assert_eq!(error("a[bc"), (ParseErrorKind::UnclosedBracket, 1..4));
+        assert_eq!(error("[az-a]"), (ParseErrorKind::InvalidRange, 2..5));
+        assert_eq!(error("[a-\\w]"), (ParseErrorKind::InvalidRange, 1..5));
+        assert_eq!(
+            error("a\\p{Klingon}"),
+            (ParseErrorKind::UnknownProperty, 1..12)
+        );
+        assert_eq!(error("\\p{L"), (ParseErrorKind::UnknownProperty, 0..4));
+        assert_eq!(error("\\x4"), (ParseErrorKind::InvalidCodePoint, 0..3));
+        assert_eq!(error("\\u{D800}"), (ParseErrorKind::InvalidCodePoint, 0..8));
+        assert_eq!(error("\\u41"), (ParseErrorKind::InvalidCodePoint, 0..2));
+        assert_eq!(error("a{3,2}"), (ParseErrorKind::InvalidRepetition, 1..6));
+        assert_eq!(error("a{,2}"), (ParseErrorKind::InvalidRepetition, 1..2));
+        assert_eq!(error("a{2"), (ParseErrorKind::InvalidRepetition, 1..3));
+        assert_eq!(error("(?x)"), (ParseErrorKind::UnknownFlag, 0..3));
+        assert_eq!(error("a(?i"), (ParseErrorKind::UnknownFlag, 1..4));
+        assert_eq!(error("(?i)*"), (ParseErrorKind::DanglingQuantifier, 4..5));
+        assert_eq!(error("a|~"), (ParseErrorKind::DanglingComplement, 2..3));
+        assert_eq!(error("~&a"), (ParseErrorKind::DanglingComplement, 0..1));
+        assert_eq!(error("~(?i)"), (ParseErrorKind::DanglingComplement, 0..5));
+        assert_eq!(
+            error("a{99999999999}"),
+            (ParseErrorKind::InvalidRepetition, 1..13)
+        );
+    }
+    #[test]
+    fn nest_limit() {
+        let limit = super::DEFAULT_NEST_LIMIT;
+        let kind = ParseErrorKind::NestLimitExceeded(limit);
+        // none of them reaches the stack end, neither in the parser nor after it
+        let deep = "(".repeat(20_000) + &")".repeat(20_000);
+        assert_eq!(parse(&deep).unwrap_err().kind, kind);
+        assert_eq!(parse(&"~".repeat(20_000)).unwrap_err().kind, kind);
+        let error = parse(&("a".to_owned() + &"*".repeat(20_000))).unwrap_err();
+        assert_eq!((error.kind, error.span), (kind, 0..20_001));
+        // the group past the limit is pointed at, a tree one level too deep as a whole
+        let error = parse(&("(".repeat(limit + 1) + &")".repeat(limit + 1))).unwrap_err();
+        assert_eq!((error.kind, error.span), (kind, limit..limit + 1));
+        let error = parse(&("(".repeat(limit) + &")".repeat(limit))).unwrap_err();
+        assert_eq!((error.kind, error.span), (kind, 0..2 * limit));
+        let builder = RegexBuilder::new().nest_limit(4);
+        assert!(builder.build("(a*)*").is_ok());
+        assert_eq!(
+            builder.build("((a*)*)*").unwrap_err().kind,
+            ParseErrorKind::NestLimitExceeded(4)
+        );
+    }
+    #[test]
+    fn error_display() {
+        let error = parse("ab[a-zz-a]").unwrap_err();
+        assert_eq!(error.pattern(), "ab[a-zz-a]");
+        assert_eq!(
+            error.to_string(),
+            "error: invalid range in character class\n  ab[a-zz-a]\n        ^^^"
+        );
+    }
+}
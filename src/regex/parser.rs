@@ -0,0 +1,405 @@
+use std::{iter::Peekable, str::CharIndices};
+
+use super::{unicode, Ast, CharClass, ParseError, ParseErrorKind};
+use crate::automate::Look;
+
+/*
+ * A recursive descent parser, the grammar from the lowest precedence to the highest:
+ *
+ *  alternation  := intersection ('|' intersection)*
+ *  intersection := concat ('&' concat)*
+ *  concat       := repeat*
+ *  repeat       := '~' repeat | atom ('*' | '+' | '?' | '{' count (',' count?)? '}')*
+ *  atom        := literal | '\' escape | '.' | '^' | '$' | class | group | flags
+ *  group       := '(' ('?' flag* ':')? alternation ')'
+ *  flags       := '(?' flag* ')'
+ *  flag        := '-'? 'i'
+ *  class       := '[' '^'? (item | item '-' item)+ ']'
+ *
+ * an escape is either one char (`\n`, `\x41`, `\u{1F600}`, `\*`), a whole class (`\w`, `\p{L}`)
+ * or, out of a class, a word boundary assertion (`\b`, `\B`)
+ *
+ * a group without `?` captures, the groups are numbered from 1 as their `(` appear
+ */
+enum Escape {
+    Char(char),
+    Class(CharClass),
+    Look(Look),
+}
+
+/*
+ * `(?i)` turns the case insensitive flag on until the end of the group it appears in, `(?-i)`
+ * turns it off, and `(?i:...)` only for the group it opens. Under the flag each literal and class
+ * is replaced by its simple case folding before reaching the Nfa.
+ */
+pub(crate) struct Parser<'a> {
+    pattern: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    case_insensitive: bool,
+    // the capture groups opened so far
+    groups: usize,
+    // the groups and complements the parser is inside of, each one is a level of recursion
+    nest: usize,
+    nest_limit: usize,
+}
+
+pub(super) const META: &str = "\\|*+?().[]{}^$&~";
+// a class also accepts these escaped, the rest of its content is literal
+pub(super) const CLASS_META: &str = "\\[]^-";
+
+impl<'a> Parser<'a> {
+    #[inline]
+    pub fn new(pattern: &'a str) -> Self {
+        Self {
+            pattern,
+            chars: pattern.char_indices().peekable(),
+            case_insensitive: false,
+            groups: 0,
+            nest: 0,
+            nest_limit: usize::MAX,
+        }
+    }
+    #[inline]
+    pub fn case_insensitive(mut self, yes: bool) -> Self {
+        self.case_insensitive = yes;
+        self
+    }
+    /*
+     * the limit stops the recursion of the parser at the groups and complements, then the depth of
+     * the whole tree is checked once, as repetitions like `a**` deepen it without any recursion
+     */
+    #[inline]
+    pub fn nest_limit(mut self, limit: usize) -> Self {
+        self.nest_limit = limit;
+        self
+    }
+    pub fn parse(mut self) -> Result<Ast, ParseError> {
+        let ast = self.alternation()?;
+        match self.chars.next() {
+            // concat only stops at '|', '&' or ')', and the loops above it consume every '|' and '&'
+            Some((i, _)) => Err(self.error(ParseErrorKind::UnbalancedParen, i)),
+            None if ast.depth() > self.nest_limit => {
+                let kind = ParseErrorKind::NestLimitExceeded(self.nest_limit);
+                Err(ParseError::new(kind, 0..self.pattern.len(), self.pattern))
+            }
+            None => Ok(ast),
+        }
+    }
+    // one more level of recursion for the group or complement at `start`
+    #[inline]
+    fn enter(&mut self, start: usize) -> Result<(), ParseError> {
+        self.nest += 1;
+        if self.nest > self.nest_limit {
+            let kind = ParseErrorKind::NestLimitExceeded(self.nest_limit);
+            return Err(ParseError::new(kind, start..start + 1, self.pattern));
+        }
+        Ok(())
+    }
+    #[inline]
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.pattern.len(), |(i, _)| *i)
+    }
+    // an error spanning from `start` to everything consumed so far
+    #[inline]
+    fn error(&mut self, kind: ParseErrorKind, start: usize) -> ParseError {
+        let end = self.offset();
+        ParseError::new(kind, start..end, self.pattern)
+    }
+    #[inline]
+    fn eat(&mut self, c: char) -> bool {
+        self.chars.next_if(|(_, v)| *v == c).is_some()
+    }
+    fn alternation(&mut self) -> Result<Ast, ParseError> {
+        let mut branches = vec![self.intersection()?];
+        while self.eat('|') {
+            branches.push(self.intersection()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Ast::Alternation(branches)
+        })
+    }
+    fn intersection(&mut self) -> Result<Ast, ParseError> {
+        let mut items = vec![self.concat()?];
+        while self.eat('&') {
+            items.push(self.concat()?);
+        }
+        Ok(if items.len() == 1 {
+            items.pop().unwrap()
+        } else {
+            Ast::Intersection(items)
+        })
+    }
+    fn concat(&mut self) -> Result<Ast, ParseError> {
+        let mut items = Vec::new();
+        while let Some((_, c)) = self.chars.peek() {
+            if matches!(c, '|' | '&' | ')') {
+                break;
+            }
+            if let Some(ast) = self.repeat()? {
+                items.push(ast);
+            }
+        }
+        Ok(match items.len() {
+            0 => Ast::Empty,
+            1 => items.pop().unwrap(),
+            _ => Ast::Concat(items),
+        })
+    }
+    // nothing for a flags directive, it leaves no trace in the AST
+    fn repeat(&mut self) -> Result<Option<Ast>, ParseError> {
+        if let Some((i, _)) = self.chars.next_if(|(_, c)| *c == '~') {
+            self.enter(i)?;
+            let ast = match self.chars.peek() {
+                None | Some((_, '|' | '&' | ')')) => None,
+                Some(_) => self.repeat()?,
+            };
+            self.nest -= 1;
+            return match ast {
+                Some(ast) => Ok(Some(Ast::Complement(Box::new(ast)))),
+                None => Err(self.error(ParseErrorKind::DanglingComplement, i)),
+            };
+        }
+        let Some(mut ast) = self.atom()? else {
+            return Ok(None);
+        };
+        while let Some((i, c)) = self
+            .chars
+            .next_if(|(_, c)| matches!(c, '*' | '+' | '?' | '{'))
+        {
+            ast = match c {
+                '*' => Ast::Star(Box::new(ast)),
+                '+' => Ast::Plus(Box::new(ast)),
+                '?' => Ast::Optional(Box::new(ast)),
+                _ => {
+                    let (min, max) = match self.counts() {
+                        Some((min, max)) if max.is_none_or(|max| min <= max) => (min, max),
+                        _ => return Err(self.error(ParseErrorKind::InvalidRepetition, i)),
+                    };
+                    Ast::Repeat {
+                        ast: Box::new(ast),
+                        min,
+                        max,
+                    }
+                }
+            };
+        }
+        Ok(Some(ast))
+    }
+    // `m}`, `m,}` or `m,n}` after the '{'
+    fn counts(&mut self) -> Option<(u32, Option<u32>)> {
+        let min = self.count()?;
+        let max = if self.eat(',') {
+            if self.eat('}') {
+                return Some((min, None));
+            }
+            Some(self.count()?)
+        } else {
+            Some(min)
+        };
+        self.eat('}').then_some((min, max))
+    }
+    fn count(&mut self) -> Option<u32> {
+        let mut digits = String::new();
+        while let Some((_, c)) = self.chars.next_if(|(_, c)| c.is_ascii_digit()) {
+            digits.push(c);
+        }
+        digits.parse().ok()
+    }
+    fn atom(&mut self) -> Result<Option<Ast>, ParseError> {
+        // concat has already checked there is something left
+        let (i, c) = self.chars.next().unwrap();
+        Ok(Some(match c {
+            '(' => return self.group(i),
+            '*' | '+' | '?' | '{' => return Err(self.error(ParseErrorKind::DanglingQuantifier, i)),
+            '\\' => match self.escape(i, false)? {
+                Escape::Char(c) => self.literal(c),
+                Escape::Class(class) => self.fold(class),
+                Escape::Look(look) => Ast::Look(look),
+            },
+            '.' => Ast::Class(CharClass::dot()),
+            '^' => Ast::Look(Look::Start),
+            '$' => Ast::Look(Look::End),
+            '[' => Ast::Class(self.class(i)?),
+            c => self.literal(c),
+        }))
+    }
+    #[inline]
+    fn literal(&self, c: char) -> Ast {
+        if self.case_insensitive {
+            match unicode::case_fold(&CharClass::new([(c, c)])) {
+                class if class.ranges().len() > 1 => Ast::Class(class),
+                _ => Ast::Literal(c),
+            }
+        } else {
+            Ast::Literal(c)
+        }
+    }
+    #[inline]
+    fn fold(&self, class: CharClass) -> Ast {
+        Ast::Class(if self.case_insensitive {
+            unicode::case_fold(&class)
+        } else {
+            class
+        })
+    }
+    // the '(' at `start` is already consumed
+    fn group(&mut self, start: usize) -> Result<Option<Ast>, ParseError> {
+        let outer = self.case_insensitive;
+        let mut capture = None;
+        if self.eat('?') {
+            let mut negated = false;
+            loop {
+                match self.chars.next() {
+                    Some((_, 'i')) => self.case_insensitive = !negated,
+                    Some((_, '-')) if !negated => negated = true,
+                    Some((_, ':')) => break,
+                    // the flags stay until the end of the enclosing group
+                    Some((_, ')')) => return Ok(None),
+                    _ => return Err(self.error(ParseErrorKind::UnknownFlag, start)),
+                }
+            }
+        } else {
+            self.groups += 1;
+            capture = Some(self.groups);
+        }
+        self.enter(start)?;
+        let ast = self.alternation()?;
+        self.nest -= 1;
+        self.case_insensitive = outer;
+        if self.eat(')') {
+            Ok(Some(match capture {
+                Some(index) => Ast::Capture(index, Box::new(ast)),
+                None => ast,
+            }))
+        } else {
+            let kind = ParseErrorKind::UnclosedParen;
+            Err(ParseError::new(kind, start..start + 1, self.pattern))
+        }
+    }
+    fn escape(&mut self, start: usize, in_class: bool) -> Result<Escape, ParseError> {
+        let meta = if in_class { CLASS_META } else { META };
+        let Some((_, c)) = self.chars.next() else {
+            return Err(self.error(ParseErrorKind::TrailingBackslash, start));
+        };
+        Ok(match c {
+            'n' => Escape::Char('\n'),
+            't' => Escape::Char('\t'),
+            'r' => Escape::Char('\r'),
+            'x' => Escape::Char(self.code_point(start, 2)?),
+            'u' => Escape::Char(self.code_point(start, 0)?),
+            'p' => Escape::Class(self.property(start)?),
+            'P' => Escape::Class(self.property(start)?.negate()),
+            'b' if !in_class => Escape::Look(Look::WordBoundary),
+            'B' if !in_class => Escape::Look(Look::NotWordBoundary),
+            c if meta.contains(c) => Escape::Char(c),
+            c => match unicode::perl(c) {
+                Some(class) => Escape::Class(class),
+                None => return Err(self.error(ParseErrorKind::UnknownEscape, start)),
+            },
+        })
+    }
+    // the text inside `{...}`, the '{' is already consumed
+    fn braced(&mut self) -> Option<String> {
+        let mut text = String::new();
+        loop {
+            match self.chars.next()? {
+                (_, '}') => return Some(text),
+                (_, c) => text.push(c),
+            }
+        }
+    }
+    // `\x41`, `\x{41}` or `\u{41}`, an unbraced code point has exactly `digits` hex digits
+    fn code_point(&mut self, start: usize, digits: usize) -> Result<char, ParseError> {
+        let hex = if self.eat('{') {
+            self.braced()
+        } else {
+            (0..digits)
+                .map(|_| self.chars.next().map(|(_, c)| c))
+                .collect()
+        };
+        match hex
+            .filter(|hex| !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()))
+            .and_then(|hex| u32::from_str_radix(&hex, 16).ok())
+            .and_then(char::from_u32)
+        {
+            Some(c) => Ok(c),
+            None => Err(self.error(ParseErrorKind::InvalidCodePoint, start)),
+        }
+    }
+    // `\pL` or `\p{Letter}`
+    fn property(&mut self, start: usize) -> Result<CharClass, ParseError> {
+        let name = if self.eat('{') {
+            self.braced()
+        } else {
+            self.chars.next().map(|(_, c)| c.to_string())
+        };
+        match name.and_then(|name| unicode::property(&name)) {
+            Some(class) => Ok(class),
+            None => Err(self.error(ParseErrorKind::UnknownProperty, start)),
+        }
+    }
+    // the '[' at `start` is already consumed
+    fn class(&mut self, start: usize) -> Result<CharClass, ParseError> {
+        let negated = self.eat('^');
+        let mut class = CharClass::default();
+        let mut first = true;
+        loop {
+            let Some((i, c)) = self.chars.next() else {
+                return Err(self.error(ParseErrorKind::UnclosedBracket, start));
+            };
+            // a ']' right after the '[' or '[^' is taken literally
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let lo = match self.class_item(i, c)? {
+                Escape::Char(c) => c,
+                Escape::Class(other) => {
+                    class.union(&other);
+                    continue;
+                }
+                Escape::Look(_) => unreachable!(),
+            };
+            let hi = match self.chars.peek() {
+                Some((_, '-')) => {
+                    self.chars.next();
+                    match self.chars.next() {
+                        None => return Err(self.error(ParseErrorKind::UnclosedBracket, start)),
+                        // a trailing '-' is literal
+                        Some((_, ']')) => {
+                            class.push(lo, lo);
+                            class.push('-', '-');
+                            break;
+                        }
+                        Some((j, c)) => match self.class_item(j, c)? {
+                            Escape::Char(c) => c,
+                            Escape::Class(_) => {
+                                return Err(self.error(ParseErrorKind::InvalidRange, i))
+                            }
+                            Escape::Look(_) => unreachable!(),
+                        },
+                    }
+                }
+                _ => lo,
+            };
+            if hi < lo {
+                return Err(self.error(ParseErrorKind::InvalidRange, i));
+            }
+            class.push(lo, hi);
+        }
+        if self.case_insensitive {
+            class = unicode::case_fold(&class);
+        }
+        Ok(if negated { class.negate() } else { class })
+    }
+    #[inline]
+    fn class_item(&mut self, i: usize, c: char) -> Result<Escape, ParseError> {
+        if c == '\\' {
+            self.escape(i, true)
+        } else {
+            Ok(Escape::Char(c))
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::{btree_map::Entry, BTreeMap};
+
+use super::{boolean, unicode, Ast, CharClass, ParseErrorKind};
+use crate::automate::{Action, Dfa, Nfa, StateMachine};
+
+/*
+ * Thompson construction, every AST node becomes a fragment with exactly one entry and one exit
+ * state, fragments are glued together only by epsilon edges (`None`), so the Nfa::closure will
+ * walk through them the same way it walks through hand written ones.
+ *
+ * a counted repetition is a copy of its fragment per count, so the size of the Nfa is not
+ * bounded by the length of the pattern, `max_states` stops the construction before it explodes.
+ *
+ * a Dfa tells a word boundary by the label it reads, so when the pattern has `\b` or `\B` the
+ * classes are split into their word and non word parts, see automate::WordSymbol
+ *
+ * `&` and `~` are built as a Dfa by the boolean module, its states and edges are copied in.
+ *
+ * a capture group is wrapped between two states recording the position they are entered at,
+ * the slots 2·n and 2·n+1 for the group n, see automate::PikeVm. A group under `&` or `~` is
+ * in the Dfa and captures nothing.
+ */
+pub(crate) struct Thompson {
+    nfa: Nfa<usize, Action<char>>,
+    states: usize,
+    max_states: usize,
+    words: Option<(CharClass, CharClass)>,
+}
+
+type Fragment = Result<(usize, usize), ParseErrorKind>;
+
+impl Thompson {
+    pub fn build(ast: &Ast, max_states: usize) -> Result<Nfa<usize, Action<char>>, ParseErrorKind> {
+        let mut thompson = Self {
+            nfa: Nfa::new(0),
+            states: 0,
+            max_states,
+            words: ast.has_word_boundary().then(|| {
+                let word = unicode::perl('w').unwrap();
+                let other = word.negate();
+                (word, other)
+            }),
+        };
+        let (start, end) = thompson.compile(ast)?;
+        thompson.nfa.start_states = [start].into();
+        thompson.nfa.add_end_state(end);
+        Ok(thompson.nfa)
+    }
+    #[inline]
+    fn state(&mut self) -> Result<usize, ParseErrorKind> {
+        if self.states >= self.max_states {
+            return Err(ParseErrorKind::TooManyStates(self.max_states));
+        }
+        let state = self.states;
+        self.states += 1;
+        self.nfa.add_states(state);
+        Ok(state)
+    }
+    #[inline]
+    fn epsilon(&mut self, from: usize, to: usize) {
+        self.nfa.add_edges(from, None, to);
+    }
+    fn compile(&mut self, ast: &Ast) -> Fragment {
+        match ast {
+            Ast::Empty => {
+                let (start, end) = (self.state()?, self.state()?);
+                self.epsilon(start, end);
+                Ok((start, end))
+            }
+            Ast::Literal(c) => {
+                let (start, end) = (self.state()?, self.state()?);
+                self.nfa.add_edges(start, Some(Action::Single(*c)), end);
+                Ok((start, end))
+            }
+            Ast::Class(class) => {
+                let (start, end) = (self.state()?, self.state()?);
+                self.class(start, class, end);
+                Ok((start, end))
+            }
+            Ast::Look(look) => {
+                let (start, end) = (self.state()?, self.state()?);
+                self.nfa.add_look(start, *look, end);
+                Ok((start, end))
+            }
+            Ast::Concat(items) => {
+                let mut iter = items.iter();
+                let (start, mut end) = self.compile(iter.next().unwrap())?;
+                for item in iter {
+                    let (s, e) = self.compile(item)?;
+                    self.epsilon(end, s);
+                    end = e;
+                }
+                Ok((start, end))
+            }
+            Ast::Alternation(branches) => {
+                let start = self.state()?;
+                let mut ends = Vec::with_capacity(branches.len());
+                for branch in branches {
+                    let (s, e) = self.compile(branch)?;
+                    self.epsilon(start, s);
+                    ends.push(e);
+                }
+                let end = self.state()?;
+                for e in ends {
+                    self.epsilon(e, end);
+                }
+                Ok((start, end))
+            }
+            Ast::Star(ast) => {
+                let start = self.state()?;
+                let (s, e) = self.compile(ast)?;
+                let end = self.state()?;
+                self.epsilon(start, s);
+                self.epsilon(start, end);
+                self.epsilon(e, s);
+                self.epsilon(e, end);
+                Ok((start, end))
+            }
+            Ast::Plus(ast) => {
+                let (s, e) = self.compile(ast)?;
+                let end = self.state()?;
+                self.epsilon(e, s);
+                self.epsilon(e, end);
+                Ok((s, end))
+            }
+            Ast::Optional(ast) => {
+                let start = self.state()?;
+                let (s, e) = self.compile(ast)?;
+                let end = self.state()?;
+                self.epsilon(start, s);
+                self.epsilon(start, end);
+                self.epsilon(e, end);
+                Ok((start, end))
+            }
+            Ast::Repeat { ast, min, max } => self.repeat(ast, *min, *max),
+            Ast::Capture(index, ast) => {
+                let start = self.state()?;
+                let (s, e) = self.compile(ast)?;
+                let end = self.state()?;
+                self.epsilon(start, s);
+                self.epsilon(e, end);
+                self.nfa.add_slot(start, 2 * index);
+                self.nfa.add_slot(end, 2 * index + 1);
+                Ok((start, end))
+            }
+            Ast::Intersection(_) | Ast::Complement(_) => {
+                let words = self.words.as_ref().map(|(word, _)| word);
+                let dfa = boolean::compile(ast, self.max_states, words)?;
+                self.embed(&dfa)
+            }
+        }
+    }
+    #[inline]
+    fn class(&mut self, from: usize, class: &CharClass, to: usize) {
+        let parts = match &self.words {
+            Some((word, other)) => vec![class.intersect(word), class.intersect(other)],
+            None => vec![class.clone()],
+        };
+        for action in parts.iter().flat_map(|part| part.actions()) {
+            self.nfa.add_edges(from, Some(action), to);
+        }
+    }
+    // a state per state of the Dfa, entered from the start and leaving to the end by epsilon edges
+    fn embed(&mut self, dfa: &Dfa<usize, Action<char>>) -> Fragment {
+        let start = self.state()?;
+        let labels = boolean::labels(dfa);
+        let mut states = BTreeMap::new();
+        let pairs = labels.keys().flat_map(|&(from, to)| [from, to]);
+        for state in std::iter::once(dfa.start_state()).chain(pairs) {
+            if let Entry::Vacant(entry) = states.entry(state) {
+                entry.insert(self.state()?);
+            }
+        }
+        let end = self.state()?;
+        self.epsilon(start, states[&dfa.start_state()]);
+        for ((from, to), class) in labels.iter() {
+            self.class(states[from], class, states[to]);
+        }
+        for (state, nfa_state) in states {
+            if dfa.is_end(&state) {
+                self.epsilon(nfa_state, end);
+            }
+        }
+        Ok((start, end))
+    }
+    /*
+     * `a{2,4}` is `aa(a(a)?)?`, every optional copy may skip straight to the end, so no input
+     * can be split between the optional copies in more than one way
+     */
+    fn repeat(&mut self, ast: &Ast, min: u32, max: Option<u32>) -> Fragment {
+        let start = self.state()?;
+        let mut current = start;
+        for _ in 0..min {
+            let (s, e) = self.compile(ast)?;
+            self.epsilon(current, s);
+            current = e;
+        }
+        match max {
+            None => {
+                let (s, e) = self.compile(&Ast::Star(Box::new(ast.clone())))?;
+                self.epsilon(current, s);
+                Ok((start, e))
+            }
+            Some(max) => {
+                // no room reserved up front, a max like 4294967295 stops at the state limit first
+                let mut skips = Vec::new();
+                for _ in min..max {
+                    let (s, e) = self.compile(ast)?;
+                    self.epsilon(current, s);
+                    skips.push(current);
+                    current = e;
+                }
+                let end = self.state()?;
+                for skip in skips {
+                    self.epsilon(skip, end);
+                }
+                self.epsilon(current, end);
+                Ok((start, end))
+            }
+        }
+    }
+}
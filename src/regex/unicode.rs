@@ -0,0 +1,142 @@
+use std::cmp::Ordering;
+
+use super::{unicode_tables::*, CharClass};
+
+// loose matching from UTS#18, case, spaces, '_' and '-' are not significant
+fn loose(name: &str) -> impl Iterator<Item = char> + '_ {
+    name.chars()
+        .filter(|c| !matches!(c, ' ' | '_' | '-'))
+        .map(|c| c.to_ascii_lowercase())
+}
+
+// `\p{...}`, a general category by its short (`Lu`) or long (`Uppercase_Letter`) name
+pub(crate) fn property(name: &str) -> Option<CharClass> {
+    GENERAL_CATEGORY
+        .iter()
+        .find(|(short, long, _)| loose(short).eq(loose(name)) || loose(long).eq(loose(name)))
+        .map(|(_, _, table)| CharClass::new(table.iter().copied()))
+}
+
+#[inline]
+fn in_table(table: Table, c: char) -> bool {
+    table
+        .binary_search_by(|&(start, end)| {
+            if end < c {
+                Ordering::Less
+            } else if start > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+#[inline]
+pub(crate) fn is_word_char(c: char) -> bool {
+    in_table(PERL_WORD, c)
+}
+
+// what can be written as itself in a printed pattern, neither the other nor the separator chars
+#[inline]
+pub(crate) fn is_printable(c: char) -> bool {
+    c == ' ' || !(in_table(GC_C, c) || in_table(GC_Z, c))
+}
+
+// `\d`, `\w` and `\s` follow the Unicode flavour of UTS#18, not the ASCII one
+pub(crate) fn perl(c: char) -> Option<CharClass> {
+    let table = match c.to_ascii_lowercase() {
+        'd' => GC_ND,
+        'w' => PERL_WORD,
+        's' => PERL_SPACE,
+        _ => return None,
+    };
+    let class = CharClass::new(table.iter().copied());
+    Some(if c.is_ascii_uppercase() {
+        class.negate()
+    } else {
+        class
+    })
+}
+
+// the class with every char of it completed by its simple case folding orbit
+pub(crate) fn case_fold(class: &CharClass) -> CharClass {
+    let mut others = Vec::new();
+    for &(start, end) in class.ranges() {
+        let from = CASE_FOLD.partition_point(|(c, _)| *c < start);
+        for (_, orbit) in CASE_FOLD[from..].iter().take_while(|(c, _)| *c <= end) {
+            others.extend(orbit.iter().map(|c| (*c, *c)));
+        }
+    }
+    let mut folded = class.clone();
+    folded.union(&CharClass::new(others));
+    folded
+}
+
+#[cfg(test)]
+mod unicode_test {
+    use super::{case_fold, is_word_char, perl, property};
+    use crate::regex::CharClass;
+
+    fn contains(class: &CharClass, c: char) -> bool {
+        class.actions().any(|action| action == c)
+    }
+
+    #[test]
+    fn property_names() {
+        assert_eq!(property("Lu"), property("Uppercase_Letter"));
+        assert_eq!(property("lu"), property("uppercase letter"));
+        assert!(property("Nd").is_some());
+        assert!(property("Klingon").is_none());
+    }
+    #[test]
+    fn simple_case_folding() {
+        let fold = |c| case_fold(&CharClass::new([(c, c)]));
+        assert_eq!(
+            fold('k'),
+            CharClass::new([('K', 'K'), ('k', 'k'), ('\u{212A}', '\u{212A}')])
+        );
+        assert_eq!(
+            fold('σ'),
+            CharClass::new([('Σ', 'Σ'), ('ς', 'ς'), ('σ', 'σ')])
+        );
+        assert_eq!(fold('ß'), CharClass::new([('ß', 'ß'), ('ẞ', 'ẞ')]));
+        // no Turkish dotless i in the simple folding of i
+        assert_eq!(fold('i'), CharClass::new([('I', 'I'), ('i', 'i')]));
+        assert_eq!(fold('1'), CharClass::new([('1', '1')]));
+        assert_eq!(
+            case_fold(&CharClass::new([('a', 'c')])),
+            CharClass::new([('a', 'c'), ('A', 'C')])
+        );
+    }
+    #[test]
+    fn perl_classes() {
+        let digit = perl('d').unwrap();
+        assert_eq!(Some(digit.clone()), property("Nd"));
+        assert_eq!(perl('D').unwrap(), digit.negate());
+        assert!(perl('q').is_none());
+        let space = perl('s').unwrap();
+        assert!(contains(&space, '\u{3000}'));
+        assert!(!contains(&space, 'a'));
+        let word = perl('w').unwrap();
+        for c in ['a', 'Z', '_', '7', 'é', 'ж', '中', '٣'] {
+            assert!(contains(&word, c), "{:?}", c);
+        }
+        assert!(!contains(&word, '-'));
+        for c in [
+            'a',
+            '_',
+            '0',
+            '9',
+            'z',
+            'é',
+            '\u{200D}',
+            '\u{10FFFF}',
+            '-',
+            ' ',
+        ] {
+            assert_eq!(is_word_char(c), contains(&word, c), "{:?}", c);
+        }
+        assert_ne!(word, CharClass::default());
+    }
+}